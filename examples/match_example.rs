@@ -18,11 +18,11 @@ fn main() {
     let mut match_points = Vec::<(PixelDescription, PixelDescription)>::new();
 
     let groups = tree_left_gray.rows/200;
-    let mut masks = Vec::<((u16, u16, u16, u16), (u16, u16, u16, u16))>::with_capacity(groups as usize);
+    let mut masks = Vec::<((usize, usize, usize, usize), (usize, usize, usize, usize))>::with_capacity(groups as usize);
 
     for i in 0..groups {
         let left_x = tree_left_gray.cols - 200 -1;
-        let right_x = 0u16;
+        let right_x = 0usize;
         let y = i * 200;
         let w = 200;
         let h = 200;
@@ -55,8 +55,8 @@ fn main() {
     let mut combined_image = Mat::new(tree_left.cols + tree_right.cols, 3500, None);
     let tree_left_cols = tree_left.cols;
     let left_tree_cols = tree_right.cols;
-    combined_image = combined_image.merge(tree_left, 0, 0);
-    combined_image = combined_image.merge(tree_right, left_tree_cols - 1, 0);
+    combined_image.merge(&tree_left, 0, 0);
+    combined_image.merge(&tree_right, left_tree_cols - 1, 0);
     
     println!("Pairs: {:?}", match_points.len());
 
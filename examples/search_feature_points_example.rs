@@ -10,7 +10,7 @@ fn main() {
     let descriptions = mat.fast_search_features(30, &mask, Direction::Horizontal);
     
     for desc in descriptions {
-        mat.draw_point(desc.coordinate, &mut vec![0u8, 255u8, 0u8, 255u8]);
+        mat.draw_point(desc.coordinate, vec![0u8, 255u8, 0u8, 255u8]);
     }
     mat.save_as_png("feature_points.png");
 }
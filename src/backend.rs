@@ -0,0 +1,488 @@
+#[cfg(feature = "opencl")]
+use std::env;
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "opencl")]
+use crate::cl::CL;
+use crate::mat::brief_pattern::Offsets;
+use crate::mat::corners::Rect;
+use crate::mat::fast::CIRCLE;
+use crate::mat::kernels::{BorderMode, Kernel, Pass};
+
+#[derive(Debug, Clone)]
+pub struct BackendError {
+    pub backend: &'static str,
+    pub message: String,
+}
+
+impl BackendError {
+    pub fn new(backend: &'static str, message: String) -> BackendError {
+        BackendError { backend, message }
+    }
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} backend: {}", self.backend, self.message)
+    }
+}
+
+impl Error for BackendError {}
+
+pub type BackendResult<T> = Result<T, BackendError>;
+
+// The pixel operations `Mat` delegates. Every backend produces the same
+// results as `CpuBackend`, which is always available.
+pub trait Backend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn crop(&self, data: &[u8], raw_width: usize, channels: usize, rect: &Rect) -> BackendResult<Vec<u8>>;
+
+    // Luma of the first three channels; images with fewer channels keep
+    // their first one.
+    fn to_gray(&self, data: &[u8], channels: usize) -> BackendResult<Vec<u8>>;
+
+    fn normalize(&self, data: &[u8], max: f32) -> BackendResult<Vec<f32>>;
+
+    // Inverse of `normalize` for Laplacian output: |value|/8*max.
+    fn recover(&self, data: &[f32], max: f32) -> BackendResult<Vec<u8>>;
+
+    // Valid region only, the result is (width - kernel width + 1) x
+    // (height - kernel height + 1).
+    fn convolute(&self, src: &[f32], width: usize, height: usize, kernel: &Kernel) -> BackendResult<Vec<f32>>;
+
+    // One border-aware correlation pass over an image of interleaved
+    // channels, each channel on its own. The output keeps the input size.
+    fn correlate(&self, src: &[f32], width: usize, height: usize, channels: usize, pass: &Pass) -> BackendResult<Vec<f32>>;
+
+    // Like `convolute` (the kernel is not flipped), but for every channel and
+    // keeping the size by reading outside samples through `border`. Rank-1
+    // kernels run as a horizontal and a vertical pass.
+    fn convolute_border(&self, src: &[f32], width: usize, height: usize, channels: usize, kernel: &Kernel, border: BorderMode)
+        -> BackendResult<Vec<f32>>
+    {
+        let passes = kernel.passes(border);
+        let mut result = self.correlate(src, width, height, channels, &passes[0])?;
+        for pass in &passes[1..] {
+            result = self.correlate(&result, width, height, channels, pass)?;
+        }
+        Ok(result)
+    }
+
+    // Median of the (2*radius + 1)² neighbourhood of every pixel and channel,
+    // edges repeat the nearest pixel.
+    fn median(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize) -> BackendResult<Vec<u8>>;
+
+    // Edge preserving smoothing: the mean of the pixels within `radius`,
+    // weighted by a Gaussian of their distance (`sigma_space`) and of their
+    // colour difference to the centre pixel (`sigma_color`). Samples outside
+    // the image are left out.
+    #[allow(clippy::too_many_arguments)]
+    fn bilateral(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize, sigma_space: f32, sigma_color: f32)
+        -> BackendResult<Vec<u8>>;
+
+    // Legacy FAST response of every pixel in `rect` of a single channel
+    // image, row by row: the sum of the 16 absolute circle differences when
+    // all four compass points and 10 contiguous circle pixels differ from the
+    // centre by more than `threshold`, 0 when the pixel is no corner.
+    fn fast_scores(&self, src: &[u8], width: usize, height: usize, rect: &Rect, threshold: usize) -> BackendResult<Vec<i32>>;
+
+    // BRIEF tests of every keypoint in one call: 1 when the A sample is
+    // brighter than the B sample, 0 when not, 2 when a sample falls outside
+    // the image. `offsets` holds either `pairs` tests shared by all keypoints
+    // or `pairs` tests per keypoint; the result has `pairs` values per keypoint.
+    fn calculate_pairs(&self, src: &[u8], width: usize, height: usize, keypoints: &[(i32, i32)], offsets: &[Offsets], pairs: usize)
+        -> BackendResult<Vec<u8>>;
+
+    // `calculate_pairs` for a single keypoint (x, y).
+    #[allow(clippy::too_many_arguments)]
+    fn calculate_pair(&self, src: &[u8], x: i32, y: i32, width: i32, height: i32, ax: &[i32], ay: &[i32], bx: &[i32], by: &[i32])
+        -> BackendResult<Vec<i32>>
+    {
+        let offsets: Vec<Offsets> = (0..ax.len()).map(|i| (ax[i], ay[i], bx[i], by[i])).collect();
+        let tests = self.calculate_pairs(src, width as usize, height as usize, &[(x, y)], &offsets, offsets.len())?;
+        Ok(tests.into_iter().map(|test| test as i32).collect())
+    }
+
+    // (result width, result height, standard deviation, recovered pixels).
+    fn laplacian(&self, src: &[u8], width: usize, height: usize, kernel: &Kernel, channels: usize)
+        -> BackendResult<(usize, usize, f32, Vec<u8>)>
+    {
+        let gray_data = self.to_gray(src, channels)?;
+        let normalized_data = self.normalize(&gray_data, 255.0)?;
+        let laplacian_data = self.convolute(&normalized_data, width, height, kernel)?;
+        let recovered_data = self.recover(&laplacian_data, 255.0)?;
+
+        let length = recovered_data.len();
+        let avg = recovered_data.iter().map(|p| *p as f64).sum::<f64>()/length as f64;
+        let variance = recovered_data.iter().map(|p| (*p as f64 - avg).powi(2)).sum::<f64>();
+        let standard_deviation = (variance/length as f64).sqrt() as f32;
+
+        let result_width = width - kernel.width() + 1;
+        let result_height = height - kernel.height() + 1;
+        Ok((result_width, result_height, standard_deviation, recovered_data))
+    }
+}
+
+pub struct CpuBackend;
+
+impl Backend for CpuBackend {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn crop(&self, data: &[u8], raw_width: usize, channels: usize, rect: &Rect) -> BackendResult<Vec<u8>> {
+        let (x, y, width, height) = *rect;
+        let mut result = Vec::<u8>::with_capacity(width*height*channels);
+        for row in y..(y + height) {
+            let start = (row*raw_width + x)*channels;
+            result.extend_from_slice(&data[start..(start + width*channels)]);
+        }
+        Ok(result)
+    }
+
+    fn to_gray(&self, data: &[u8], channels: usize) -> BackendResult<Vec<u8>> {
+        if channels < 3 {
+            return Ok(data.chunks(channels).map(|pixel| pixel[0]).collect());
+        }
+        Ok(data.chunks(channels).map(|pixel| {
+            (pixel[0] as f32*0.299 + pixel[1] as f32*0.587 + pixel[2] as f32*0.114) as u8
+        }).collect())
+    }
+
+    fn normalize(&self, data: &[u8], max: f32) -> BackendResult<Vec<f32>> {
+        Ok(data.iter().map(|value| *value as f32/max).collect())
+    }
+
+    fn recover(&self, data: &[f32], max: f32) -> BackendResult<Vec<u8>> {
+        Ok(data.iter().map(|value| (value.abs()/8.0*max) as u8).collect())
+    }
+
+    fn convolute(&self, src: &[f32], width: usize, height: usize, kernel: &Kernel) -> BackendResult<Vec<f32>> {
+        let (kernel_width, kernel_height) = (kernel.width(), kernel.height());
+        let result_width = width - kernel_width + 1;
+        let result_height = height - kernel_height + 1;
+        let values = kernel.flatten();
+
+        let mut result = Vec::<f32>::with_capacity(result_width*result_height);
+        for y in 0..result_height {
+            for x in 0..result_width {
+                let mut value = 0f32;
+                for ky in 0..kernel_height {
+                    for kx in 0..kernel_width {
+                        value += values[ky*kernel_width + kx]*src[(y + ky)*width + x + kx];
+                    }
+                }
+                result.push(value);
+            }
+        }
+        Ok(result)
+    }
+
+    fn correlate(&self, src: &[f32], width: usize, height: usize, channels: usize, pass: &Pass) -> BackendResult<Vec<f32>> {
+        let (anchor_x, anchor_y) = (pass.anchor.0 as i64, pass.anchor.1 as i64);
+        let constant = pass.border.constant();
+        // Source rows and columns each kernel row and column reads, per
+        // output position.
+        let columns: Vec<Vec<Option<usize>>> = (0..width as i64).map(|x| {
+            (0..pass.width as i64).map(|kx| pass.border.index(x + kx - anchor_x, width)).collect()
+        }).collect();
+        let rows: Vec<Vec<Option<usize>>> = (0..height as i64).map(|y| {
+            (0..pass.height as i64).map(|ky| pass.border.index(y + ky - anchor_y, height)).collect()
+        }).collect();
+
+        let mut result = Vec::<f32>::with_capacity(src.len());
+        for source_rows in rows.iter() {
+            for source_columns in columns.iter() {
+                for channel in 0..channels {
+                    let mut value = 0f32;
+                    for (ky, row) in source_rows.iter().enumerate() {
+                        for (kx, column) in source_columns.iter().enumerate() {
+                            let sample = match (row, column) {
+                                (Some(row), Some(column)) => src[(row*width + column)*channels + channel],
+                                _ => constant
+                            };
+                            value += pass.values[ky*pass.width + kx]*sample;
+                        }
+                    }
+                    result.push(value);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn fast_scores(&self, src: &[u8], width: usize, height: usize, rect: &Rect, threshold: usize) -> BackendResult<Vec<i32>> {
+        let (x, y, rect_width, rect_height) = *rect;
+        let threshold = threshold.min(i32::MAX as usize) as i32;
+        let mut result = vec![0i32; rect_width*rect_height];
+        for py in y..(y + rect_height) {
+            for px in x..(x + rect_width) {
+                if px < 3 || py < 3 || px + 3 >= width || py + 3 >= height {
+                    continue;
+                }
+                let center = src[py*width + px] as i32;
+                let mut differences = [0i32; 16];
+                for (i, (dx, dy)) in CIRCLE.iter().enumerate() {
+                    let index = (py as i32 + dy) as usize*width + (px as i32 + dx) as usize;
+                    differences[i] = (src[index] as i32 - center).abs();
+                }
+                if (0..16).step_by(4).any(|i| differences[i] <= threshold) {
+                    continue;
+                }
+                // The circle is walked up to 25 pixels, so arcs crossing the
+                // start are counted.
+                let mut run = 0;
+                let mut longest = 0;
+                for i in 0..25 {
+                    if differences[i%16] > threshold {
+                        run += 1;
+                        longest = longest.max(run);
+                    } else {
+                        run = 0;
+                    }
+                }
+                if longest >= 10 {
+                    result[(py - y)*rect_width + px - x] = differences.iter().sum();
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn calculate_pairs(&self, src: &[u8], width: usize, height: usize, keypoints: &[(i32, i32)], offsets: &[Offsets], pairs: usize)
+        -> BackendResult<Vec<u8>>
+    {
+        let (width, height) = (width as i32, height as i32);
+        let inside = |px: i32, py: i32| px >= 0 && py >= 0 && px < width && py < height;
+        let shared = offsets.len() == pairs;
+        let mut result = Vec::<u8>::with_capacity(keypoints.len()*pairs);
+        for (k, &(x, y)) in keypoints.iter().enumerate() {
+            let tests = if shared { offsets } else { &offsets[k*pairs..(k + 1)*pairs] };
+            result.extend(tests.iter().map(|&(ax, ay, bx, by)| {
+                let (a_x, a_y, b_x, b_y) = (x + ax, y + ay, x + bx, y + by);
+                if !inside(a_x, a_y) || !inside(b_x, b_y) {
+                    return 2;
+                }
+                (src[(a_y*width + a_x) as usize] > src[(b_y*width + b_x) as usize]) as u8
+            }));
+        }
+        Ok(result)
+    }
+
+    fn median(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize) -> BackendResult<Vec<u8>> {
+        if width == 0 || height == 0 || radius == 0 {
+            return Ok(src.to_vec());
+        }
+        Ok(median_histogram(src, width, height, channels, radius))
+    }
+
+    fn bilateral(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize, sigma_space: f32, sigma_color: f32)
+        -> BackendResult<Vec<u8>>
+    {
+        let space_coefficient = -0.5/(sigma_space*sigma_space).max(f32::EPSILON);
+        let color_coefficient = -0.5/(sigma_color*sigma_color).max(f32::EPSILON);
+        let (w, h, r) = (width as i64, height as i64, radius as i64);
+        let mut result = Vec::<u8>::with_capacity(src.len());
+        let mut sums = vec![0f32; channels];
+        for y in 0..h {
+            for x in 0..w {
+                let center = &src[((y*w + x) as usize)*channels..((y*w + x) as usize + 1)*channels];
+                let mut total = 0f32;
+                sums.iter_mut().for_each(|sum| *sum = 0.0);
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (sx, sy) = (x + dx, y + dy);
+                        if dx*dx + dy*dy > r*r || sx < 0 || sy < 0 || sx >= w || sy >= h {
+                            continue;
+                        }
+                        let sample = &src[((sy*w + sx) as usize)*channels..((sy*w + sx) as usize + 1)*channels];
+                        let difference: f32 = sample.iter().zip(center.iter()).map(|(a, b)| (*a as f32 - *b as f32).powi(2)).sum();
+                        let weight = ((dx*dx + dy*dy) as f32*space_coefficient + difference*color_coefficient).exp();
+                        total += weight;
+                        for (sum, value) in sums.iter_mut().zip(sample.iter()) {
+                            *sum += weight*(*value as f32);
+                        }
+                    }
+                }
+                result.extend(sums.iter().map(|sum| (sum/total).round().clamp(0.0, 255.0) as u8));
+            }
+        }
+        Ok(result)
+    }
+}
+
+// Huang's algorithm: a histogram of the window slides along each row, one
+// column out and one in, and the median follows it from the last position.
+// Per pixel this costs O(radius) instead of sorting O(radius²) samples.
+fn median_histogram(src: &[u8], width: usize, height: usize, channels: usize, radius: usize) -> Vec<u8> {
+    let (w, h, r) = (width as i64, height as i64, radius as i64);
+    let target = ((2*radius + 1)*(2*radius + 1)/2) as u32;
+    let sample = |x: i64, y: i64, channel: usize| src[((y.clamp(0, h - 1)*w + x.clamp(0, w - 1)) as usize)*channels + channel] as usize;
+    let mut result = vec![0u8; src.len()];
+    for y in 0..h {
+        for channel in 0..channels {
+            let mut histogram = [0u32; 256];
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    histogram[sample(dx, y + dy, channel)] += 1;
+                }
+            }
+            // `below` counts the window values under `median`.
+            let (mut median, mut below) = (0usize, 0u32);
+            for x in 0..w {
+                if x > 0 {
+                    for dy in -r..=r {
+                        let out = sample(x - r - 1, y + dy, channel);
+                        histogram[out] -= 1;
+                        if out < median {
+                            below -= 1;
+                        }
+                        let entering = sample(x + r, y + dy, channel);
+                        histogram[entering] += 1;
+                        if entering < median {
+                            below += 1;
+                        }
+                    }
+                }
+                while below > target {
+                    median -= 1;
+                    below -= histogram[median];
+                }
+                while below + histogram[median] <= target {
+                    below += histogram[median];
+                    median += 1;
+                }
+                result[((y*w + x) as usize)*channels + channel] = median as u8;
+            }
+        }
+    }
+    result
+}
+
+// Largest median radius the OpenCL kernel is used for.
+#[cfg(feature = "opencl")]
+const CL_MEDIAN_MAX_RADIUS: usize = 5;
+
+#[cfg(feature = "opencl")]
+impl Backend for CL {
+    fn name(&self) -> &'static str {
+        "opencl"
+    }
+
+    fn crop(&self, data: &[u8], raw_width: usize, channels: usize, rect: &Rect) -> BackendResult<Vec<u8>> {
+        let (x, y, width, height) = *rect;
+        self.cl_crop(data, raw_width as i32, x as i32, y as i32, width as i32, height as i32, channels as i32)
+            .map_err(opencl_error)
+    }
+
+    fn to_gray(&self, data: &[u8], channels: usize) -> BackendResult<Vec<u8>> {
+        // The kernel always reads three channels.
+        if channels < 3 {
+            return CpuBackend.to_gray(data, channels);
+        }
+        self.cl_to_gray(data, channels).map_err(opencl_error)
+    }
+
+    fn normalize(&self, data: &[u8], max: f32) -> BackendResult<Vec<f32>> {
+        self.cl_normalize(data, max).map_err(opencl_error)
+    }
+
+    fn recover(&self, data: &[f32], max: f32) -> BackendResult<Vec<u8>> {
+        self.cl_recover(data, max).map_err(opencl_error)
+    }
+
+    fn convolute(&self, src: &[f32], width: usize, height: usize, kernel: &Kernel) -> BackendResult<Vec<f32>> {
+        self.cl_convolute(src, width, height, kernel).map_err(opencl_error)
+    }
+
+    fn correlate(&self, src: &[f32], width: usize, height: usize, channels: usize, pass: &Pass) -> BackendResult<Vec<f32>> {
+        let src = self.upload(src, width, height, channels).map_err(opencl_error)?;
+        self.correlate_image(&src, pass).and_then(|result| self.download(&result)).map_err(opencl_error)
+    }
+
+    fn convolute_border(&self, src: &[f32], width: usize, height: usize, channels: usize, kernel: &Kernel, border: BorderMode)
+        -> BackendResult<Vec<f32>>
+    {
+        self.cl_convolute_border(src, width, height, channels, kernel, border).map_err(opencl_error)
+    }
+
+    fn fast_scores(&self, src: &[u8], width: usize, height: usize, rect: &Rect, threshold: usize) -> BackendResult<Vec<i32>> {
+        self.cl_fast_scores(src, width, height, rect, threshold).map_err(opencl_error)
+    }
+
+    fn calculate_pairs(&self, src: &[u8], width: usize, height: usize, keypoints: &[(i32, i32)], offsets: &[Offsets], pairs: usize)
+        -> BackendResult<Vec<u8>>
+    {
+        self.cl_calculate_pairs(src, width, height, keypoints, offsets, pairs).map_err(opencl_error)
+    }
+
+    fn median(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize) -> BackendResult<Vec<u8>> {
+        // The kernel costs O(radius²) per pixel, the CPU histogram O(radius).
+        if radius > CL_MEDIAN_MAX_RADIUS {
+            return CpuBackend.median(src, width, height, channels, radius);
+        }
+        self.cl_median(src, width, height, channels, radius).map_err(opencl_error)
+    }
+
+    fn bilateral(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize, sigma_space: f32, sigma_color: f32)
+        -> BackendResult<Vec<u8>>
+    {
+        self.cl_bilateral(src, width, height, channels, radius, sigma_space, sigma_color).map_err(opencl_error)
+    }
+}
+
+#[cfg(feature = "opencl")]
+fn opencl_error(error: ocl::Error) -> BackendError {
+    BackendError::new("opencl", error.to_string())
+}
+
+// OpenCL when a device can be set up, the CPU otherwise. Setting the
+// `EVA_BACKEND` environment variable to `cpu` skips the OpenCL probe.
+#[cfg(feature = "opencl")]
+fn select_backend() -> Box<dyn Backend> {
+    if env::var("EVA_BACKEND").map(|v| v == "cpu").unwrap_or(false) {
+        info!("Using the CPU backend, requested by EVA_BACKEND");
+        return Box::new(CpuBackend);
+    }
+    match CL::try_new() {
+        Ok(cl) => {
+            info!("Using the OpenCL backend");
+            Box::new(cl)
+        },
+        Err(e) => {
+            info!("OpenCL is not available ({}), using the CPU backend", e);
+            Box::new(CpuBackend)
+        }
+    }
+}
+
+#[cfg(not(feature = "opencl"))]
+fn select_backend() -> Box<dyn Backend> {
+    Box::new(CpuBackend)
+}
+
+lazy_static! {
+    static ref DEFAULT_BACKEND: Box<dyn Backend> = select_backend();
+}
+
+pub fn default_backend() -> &'static dyn Backend {
+    DEFAULT_BACKEND.as_ref()
+}
+
+// Runs `operation` on the default backend and, if that fails, once more on
+// the CPU.
+pub fn run<T, F: Fn(&dyn Backend) -> BackendResult<T>>(operation: F) -> T {
+    run_on(default_backend(), operation)
+}
+
+pub fn run_on<T, F: Fn(&dyn Backend) -> BackendResult<T>>(backend: &dyn Backend, operation: F) -> T {
+    match operation(backend) {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("{}, retrying on the CPU", e);
+            operation(&CpuBackend).expect("The CPU backend does not fail")
+        }
+    }
+}
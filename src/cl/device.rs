@@ -0,0 +1,265 @@
+use std::error::Error;
+use std::ffi::NulError;
+use std::fmt;
+use std::thread;
+
+use ocl::core;
+use ocl::enums::{DeviceInfo as DeviceInfoKind, PlatformInfo};
+use ocl::flags::DeviceType;
+
+use super::CL;
+
+const MAX_DEFAULT_QUEUES: usize = 8;
+
+#[derive(Debug)]
+pub enum ClError {
+    NoPlatform,
+    // No device matched the builder's criteria, described by the string.
+    NoDevice(String),
+    // The program did not compile; `log` is the compiler output.
+    Build { device: String, log: String },
+    Api(ocl::Error),
+}
+
+impl fmt::Display for ClError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ClError::NoPlatform => write!(f, "No OpenCL platform found"),
+            ClError::NoDevice(criteria) => write!(f, "No OpenCL device matches {}", criteria),
+            ClError::Build { device, log } => write!(f, "OpenCL program build failed on {}:\n{}", device, log),
+            ClError::Api(e) => write!(f, "{}", e)
+        }
+    }
+}
+
+impl Error for ClError {}
+
+impl From<ocl::Error> for ClError {
+    fn from(e: ocl::Error) -> ClError {
+        ClError::Api(e)
+    }
+}
+
+impl From<core::Error> for ClError {
+    fn from(e: core::Error) -> ClError {
+        ClError::Api(e.into())
+    }
+}
+
+// `NulError` is as old as `std::ffi`, clippy only sees its newer `alloc`
+// re-export.
+#[allow(clippy::incompatible_msrv)]
+impl From<NulError> for ClError {
+    fn from(e: NulError) -> ClError {
+        ClError::Api(e.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Any,
+    Cpu,
+    Gpu,
+    Accelerator,
+}
+
+impl DeviceKind {
+    fn device_type(&self) -> Option<DeviceType> {
+        match self {
+            DeviceKind::Any => None,
+            DeviceKind::Cpu => Some(DeviceType::CPU),
+            DeviceKind::Gpu => Some(DeviceType::GPU),
+            DeviceKind::Accelerator => Some(DeviceType::ACCELERATOR)
+        }
+    }
+
+    fn of(device_id: core::DeviceId) -> DeviceKind {
+        match core::get_device_info(device_id, DeviceInfoKind::Type) {
+            Ok(core::DeviceInfoResult::Type(t)) if t.contains(DeviceType::GPU) => DeviceKind::Gpu,
+            Ok(core::DeviceInfoResult::Type(t)) if t.contains(DeviceType::CPU) => DeviceKind::Cpu,
+            Ok(core::DeviceInfoResult::Type(t)) if t.contains(DeviceType::ACCELERATOR) => DeviceKind::Accelerator,
+            _ => DeviceKind::Any
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub platform_index: usize,
+    pub platform_name: String,
+    // Index among all devices of the platform.
+    pub device_index: usize,
+    pub name: String,
+    pub vendor: String,
+    pub kind: DeviceKind,
+}
+
+pub(crate) fn device_name(device_id: core::DeviceId) -> String {
+    core::get_device_info(device_id, DeviceInfoKind::Name)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|_| "unknown device".to_string())
+}
+
+fn platform_name(platform_id: core::PlatformId) -> String {
+    core::get_platform_info(platform_id, PlatformInfo::Name)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|_| "unknown platform".to_string())
+}
+
+fn platforms() -> Result<Vec<core::PlatformId>, ClError> {
+    let platforms = core::get_platform_ids()?;
+    if platforms.is_empty() {
+        return Err(ClError::NoPlatform);
+    }
+    Ok(platforms)
+}
+
+// Every device of every platform, in platform then device order.
+pub fn list_devices() -> Result<Vec<DeviceInfo>, ClError> {
+    let mut devices = Vec::<DeviceInfo>::new();
+    for (platform_index, platform_id) in platforms()?.into_iter().enumerate() {
+        let platform_name = platform_name(platform_id);
+        // A platform without devices reports an error, it just adds none.
+        let device_ids = core::get_device_ids(platform_id, Some(DeviceType::ALL), None).unwrap_or_default();
+        for (device_index, device_id) in device_ids.into_iter().enumerate() {
+            devices.push(DeviceInfo {
+                platform_index,
+                platform_name: platform_name.clone(),
+                device_index,
+                name: device_name(device_id),
+                vendor: core::get_device_info(device_id, DeviceInfoKind::Vendor).map(|v| v.to_string()).unwrap_or_default(),
+                kind: DeviceKind::of(device_id),
+            });
+        }
+    }
+    Ok(devices)
+}
+
+#[derive(Debug, Clone)]
+enum Selector {
+    Index(usize),
+    // Case-insensitive substring of the name.
+    Name(String),
+}
+
+impl Selector {
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Selector::Index(i) => *i == index,
+            Selector::Name(n) => name.to_lowercase().contains(&n.to_lowercase())
+        }
+    }
+}
+
+// Chooses the platform and device `CL` runs on. Without any criteria this
+// is the default platform (`OCL_DEFAULT_PLATFORM_IDX`) and its first device.
+// A device index counts the devices left after the kind and name filters.
+#[derive(Debug, Clone)]
+pub struct ClBuilder {
+    platform: Option<Selector>,
+    // Case-insensitive substring of the device name.
+    device_name: Option<String>,
+    device_index: usize,
+    kind: DeviceKind,
+    build_options: String,
+    queues: usize,
+}
+
+impl Default for ClBuilder {
+    fn default() -> ClBuilder {
+        let queues = thread::available_parallelism().map(|n| n.get().min(MAX_DEFAULT_QUEUES)).unwrap_or(1);
+        ClBuilder { platform: None, device_name: None, device_index: 0, kind: DeviceKind::Any, build_options: String::new(), queues }
+    }
+}
+
+impl ClBuilder {
+    pub fn new() -> ClBuilder {
+        ClBuilder::default()
+    }
+
+    pub fn platform_index(mut self, index: usize) -> ClBuilder {
+        self.platform = Some(Selector::Index(index));
+        self
+    }
+
+    pub fn platform_name(mut self, name: &str) -> ClBuilder {
+        self.platform = Some(Selector::Name(name.to_string()));
+        self
+    }
+
+    pub fn device_index(mut self, index: usize) -> ClBuilder {
+        self.device_index = index;
+        self
+    }
+
+    pub fn device_name(mut self, name: &str) -> ClBuilder {
+        self.device_name = Some(name.to_lowercase());
+        self
+    }
+
+    pub fn device_kind(mut self, kind: DeviceKind) -> ClBuilder {
+        self.kind = kind;
+        self
+    }
+
+    // Passed to the OpenCL compiler, e.g. "-cl-fast-relaxed-math".
+    pub fn build_options(mut self, options: &str) -> ClBuilder {
+        self.build_options = options.to_string();
+        self
+    }
+
+    // Command queues shared by the threads using this `CL`, one per worker
+    // thread avoids them waiting on each other. Defaults to the number of
+    // CPUs, at most 8.
+    pub fn queues(mut self, queues: usize) -> ClBuilder {
+        self.queues = queues.max(1);
+        self
+    }
+
+    fn criteria(&self) -> String {
+        format!("platform {:?}, device {:?} #{}, kind {:?}", self.platform, self.device_name, self.device_index, self.kind)
+    }
+
+    // Candidate platforms, the default one first when none was chosen.
+    fn candidate_platforms(&self) -> Result<Vec<core::PlatformId>, ClError> {
+        let all = platforms()?;
+        match &self.platform {
+            Some(selector) => Ok(all.into_iter().enumerate()
+                .filter(|(i, p)| selector.matches(*i, &platform_name(*p)))
+                .map(|(_, p)| p)
+                .collect()),
+            None => {
+                let default = core::default_platform()?;
+                let mut ordered = vec![default];
+                ordered.extend(all.into_iter().filter(|p| *p != default));
+                Ok(ordered)
+            }
+        }
+    }
+
+    pub fn select(&self) -> Result<(core::PlatformId, core::DeviceId), ClError> {
+        let mut candidates = Vec::<(core::PlatformId, core::DeviceId)>::new();
+        for platform_id in self.candidate_platforms()? {
+            let device_ids = core::get_device_ids(platform_id, Some(self.kind.device_type().unwrap_or(DeviceType::ALL)), None)
+                .unwrap_or_default();
+            for device_id in device_ids {
+                let matches = match &self.device_name {
+                    Some(name) => device_name(device_id).to_lowercase().contains(name),
+                    None => true
+                };
+                if matches {
+                    candidates.push((platform_id, device_id));
+                }
+            }
+        }
+        match candidates.get(self.device_index) {
+            Some(candidate) => Ok(*candidate),
+            None => Err(ClError::NoDevice(self.criteria()))
+        }
+    }
+
+    pub fn build(&self) -> Result<CL, ClError> {
+        let (platform_id, device_id) = self.select()?;
+        CL::from_device(platform_id, device_id, &self.build_options, self.queues)
+    }
+}
@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
+
+use ocl::core::{self, OclPrm};
+
+// Released device buffers by (queue index, size in bytes), reused by later
+// allocations. A buffer only goes back to the queue that last used it: that
+// queue is in order, so commands still pending on the buffer finish before
+// anything enqueued by its next owner, while another queue could overtake
+// them.
+pub(crate) type BufferPool = Arc<Mutex<HashMap<(usize, usize), Vec<core::Mem>>>>;
+
+// Buffers kept per size; pipelines alternate between a handful of sizes.
+const POOLED_PER_SIZE: usize = 4;
+
+// Image data living on the device. Kernels chained through `ClImage`s only
+// copy to the host when `CL::download` is called. The buffer goes back to
+// the pool of its `CL`, under the queue it was created on, when the image
+// is dropped.
+pub struct ClImage<T: OclPrm> {
+    mem: Option<core::Mem>,
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pool: BufferPool,
+    queue: usize,
+    element: PhantomData<T>,
+}
+
+impl<T: OclPrm> ClImage<T> {
+    pub(crate) fn new(mem: core::Mem, width: usize, height: usize, channels: usize, pool: BufferPool, queue: usize) -> ClImage<T> {
+        ClImage { mem: Some(mem), width, height, channels, pool, queue, element: PhantomData }
+    }
+
+    pub fn len(&self) -> usize {
+        self.width*self.height*self.channels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn mem(&self) -> &core::Mem {
+        self.mem.as_ref().unwrap()
+    }
+}
+
+impl<T: OclPrm> Drop for ClImage<T> {
+    fn drop(&mut self) {
+        let bytes = self.len()*size_of::<T>();
+        if let (Some(mem), Ok(mut pool)) = (self.mem.take(), self.pool.lock()) {
+            let buffers = pool.entry((self.queue, bytes)).or_default();
+            if buffers.len() < POOLED_PER_SIZE {
+                buffers.push(mem);
+            }
+        }
+    }
+}
@@ -1,287 +1,589 @@
 extern crate ocl;
 
+mod device;
+mod image;
+
 use std::ffi::CString;
+use std::mem::size_of;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use ocl::builders::ContextProperties;
 use ocl::{core, flags};
-use ocl::enums::ArgVal;
+use ocl::core::OclPrm;
+use ocl::enums::{ArgVal, ProgramBuildInfo};
 use std::time::Instant;
 
-use crate::mat::kernels::Kernel;
+pub use self::device::{ClBuilder, ClError, DeviceInfo, DeviceKind, list_devices};
+pub use self::image::ClImage;
+use self::image::BufferPool;
+
+use crate::mat::brief_pattern::Offsets;
+use crate::mat::corners::Rect;
+use crate::mat::kernels::{BorderMode, Kernel, Pass};
+
+const PROGRAM_SOURCE: &str = r#"
+    __kernel void crop(__global uchar* result, __global uchar* data, int x, int y, int width, int height, int channels, int data_width) {
+        int new_x = get_global_id(0);
+        int new_y = get_global_id(1);
+        int new_index = new_y * width * channels + new_x * channels;
+
+        int old_x = new_x + x;
+        int old_y = new_y + y;
+        int old_index = old_x * channels + old_y * data_width * channels;
+        result[new_index] = data[old_index];
+        for (int i=0;i<channels;i++) {
+            result[new_index+i] = data[old_index+i];
+        } 
+    }
 
-#[derive(Debug, Clone)]
-pub struct CL {
-    context: ocl::core::Context,
-    program: ocl::core::Program,
-    queue: ocl::core::CommandQueue
-}
+    __kernel void convolute(__global float* result, __global float* data, __global float* kernel_array, int width, int height, int kernel_width, int kernel_height) {
+        int result_x = get_global_id(0);
+        int result_y = get_global_id(1);
 
-impl CL {
-    pub fn new() -> CL {
-        let src = r#"
-            __kernel void crop(__global uchar* result, __global uchar* data, int x, int y, int width, int height, int channels, int data_width) {
-                int new_x = get_global_id(0);
-                int new_y = get_global_id(1);
-                int new_index = new_y * width * channels + new_x * channels;
-
-                int old_x = new_x + x;
-                int old_y = new_y + y;
-                int old_index = old_x * channels + old_y * data_width * channels;
-                result[new_index] = data[old_index];
-                for (int i=0;i<channels;i++) {
-                    result[new_index+i] = data[old_index+i];
-                } 
-            }
+        int result_width = width - kernel_width + 1;
 
-            __kernel void convolute(__global float* result, __global float* data, __global float* kernel_array, int width, int height, int kernel_width) {
-                int result_x = get_global_id(0);
-                int result_y = get_global_id(1);
+        int result_index = result_y * result_width + result_x;
 
-                int result_width = width - kernel_width + 1;
+        float result_value = 0.0;
+        for (int ky=0;ky<kernel_height;ky++) {
+            for (int kx=0;kx<kernel_width;kx++) {
+                int data_x = result_x + kx;
+                int data_y = result_y + ky;
 
-                int result_index = result_y * result_width + result_x;
+                int data_index = data_y * width + data_x;
+                
+                int k_index = kx + ky * kernel_width;
+                float k_value = kernel_array[k_index];
+                float data_value = data[data_index];
 
-                float result_value = 0.0;
-                for (int ky=0;ky<kernel_width;ky++) {
-                    for (int kx=0;kx<kernel_width;kx++) {
-                        int data_x = result_x + kx;
-                        int data_y = result_y + ky;
+                result_value = result_value + k_value * data_value;
+            }
+        }
+
+        result[result_index] = result_value;
+    }
 
-                        int data_index = data_y * width + data_x;
-                        
-                        int k_index = kx + ky * kernel_width;
-                        float k_value = kernel_array[k_index];
-                        float data_value = data[data_index];
+    // Sample index of position i in a line of n samples, -1 for the
+    // constant. Mirrors `BorderMode::index`.
+    int border_index(int i, int n, int border) {
+        if (i >= 0 && i < n) {
+            return i;
+        }
+        if (border == 1) {
+            return clamp(i, 0, n - 1);
+        }
+        if (border == 2) {
+            int period = 2*n;
+            int m = ((i % period) + period) % period;
+            return m < n ? m : period - 1 - m;
+        }
+        if (border == 3) {
+            return ((i % n) + n) % n;
+        }
+        return -1;
+    }
 
-                        result_value = result_value + k_value * data_value;
+    // Same size correlation of every channel, outside samples are read
+    // through the border mode.
+    __kernel void correlate(
+        __global float* result,
+        __global float* data,
+        __global float* kernel_array,
+        int width,
+        int height,
+        int channels,
+        int kernel_width,
+        int kernel_height,
+        int anchor_x,
+        int anchor_y,
+        int border,
+        float constant
+    ) {
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+
+        for (int c=0;c<channels;c++) {
+            float value = 0.0f;
+            for (int ky=0;ky<kernel_height;ky++) {
+                int row = border_index(y + ky - anchor_y, height, border);
+                for (int kx=0;kx<kernel_width;kx++) {
+                    int column = border_index(x + kx - anchor_x, width, border);
+                    float sample = constant;
+                    if (row >= 0 && column >= 0) {
+                        sample = data[(row*width + column)*channels + c];
                     }
+                    value += kernel_array[ky*kernel_width + kx]*sample;
                 }
-
-                result[result_index] = result_value;
             }
+            result[(y*width + x)*channels + c] = value;
+        }
+    }
 
-            __kernel void to_gray(__global uchar* result, __global uchar* data, int channels) {
-                int base_index = get_global_id(0);
-                uchar r = (float) data[base_index * channels];
-                uchar g = (float) data[base_index * channels + 1];
-                uchar b = (float) data[base_index * channels + 2];
-                uchar gray = (r*0.299 + g*0.587 + b*0.114);
+    // Median of the (2*radius + 1)² window per channel, edges clamped. The
+    // value is found by a binary search over 0-255 counting the window
+    // samples at or below it, so no histogram is kept. That reads the window
+    // 8 times, O(radius²) per pixel unlike the CPU's sliding histogram, so
+    // the backend only sends small radii here.
+    __kernel void median(__global uchar* result, __global uchar* data, int width, int height, int channels, int radius) {
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+        int target = ((2*radius + 1)*(2*radius + 1))/2;
+
+        for (int c=0;c<channels;c++) {
+            int low = 0;
+            int high = 255;
+            while (low < high) {
+                int middle = (low + high)/2;
+                int count = 0;
+                for (int dy=-radius;dy<=radius;dy++) {
+                    int row = clamp(y + dy, 0, height - 1);
+                    for (int dx=-radius;dx<=radius;dx++) {
+                        int column = clamp(x + dx, 0, width - 1);
+                        count += data[(row*width + column)*channels + c] <= middle;
+                    }
+                }
+                if (count > target) {
+                    high = middle;
+                } else {
+                    low = middle + 1;
+                }
+            }
+            result[(y*width + x)*channels + c] = low;
+        }
+    }
 
-                result[base_index] = gray;
+    // Bilateral filter over a disc of `radius`, see `Backend::bilateral`.
+    // Up to four channels.
+    __kernel void bilateral(
+        __global uchar* result,
+        __global uchar* data,
+        int width,
+        int height,
+        int channels,
+        int radius,
+        float space_coefficient,
+        float color_coefficient
+    ) {
+        int x = get_global_id(0);
+        int y = get_global_id(1);
+        int center = (y*width + x)*channels;
+
+        float sums[4] = {0.0f, 0.0f, 0.0f, 0.0f};
+        float total = 0.0f;
+        for (int dy=-radius;dy<=radius;dy++) {
+            for (int dx=-radius;dx<=radius;dx++) {
+                int sx = x + dx;
+                int sy = y + dy;
+                if (dx*dx + dy*dy > radius*radius || sx < 0 || sy < 0 || sx >= width || sy >= height) {
+                    continue;
+                }
+                int sample = (sy*width + sx)*channels;
+                float difference = 0.0f;
+                for (int c=0;c<channels;c++) {
+                    float d = (float)data[sample + c] - (float)data[center + c];
+                    difference += d*d;
+                }
+                float weight = exp((float)(dx*dx + dy*dy)*space_coefficient + difference*color_coefficient);
+                total += weight;
+                for (int c=0;c<channels;c++) {
+                    sums[c] += weight*data[sample + c];
+                }
             }
+        }
+        for (int c=0;c<channels;c++) {
+            result[center + c] = (uchar)clamp(round(sums[c]/total), 0.0f, 255.0f);
+        }
+    }
 
-            __kernel void normalize_u8(__global float* result, __global uchar* data, float max) {
-                int index = get_global_id(0);
-                float value = (float) data[index];
-                float v = value/max;
+    __kernel void to_gray(__global uchar* result, __global uchar* data, int channels) {
+        int base_index = get_global_id(0);
+        uchar r = (float) data[base_index * channels];
+        uchar g = (float) data[base_index * channels + 1];
+        uchar b = (float) data[base_index * channels + 2];
+        uchar gray = (r*0.299 + g*0.587 + b*0.114);
 
-                result[index] = v;
-            }
+        result[base_index] = gray;
+    }
 
-            __kernel void recover_u8(__global uchar* result, __global float* data, float max) {
-                int index = get_global_id(0);
-                float raw_value = data[index];
-                if (raw_value < 0.0) {
-                    raw_value = -raw_value;
-                }
-                uchar value = (uchar) (raw_value / 8.0 * max);
+    __kernel void normalize_u8(__global float* result, __global uchar* data, float max) {
+        int index = get_global_id(0);
+        float value = (float) data[index];
+        float v = value/max;
+
+        result[index] = v;
+    }
+
+    __kernel void recover_u8(__global uchar* result, __global float* data, float max) {
+        int index = get_global_id(0);
+        float raw_value = data[index];
+        if (raw_value < 0.0) {
+            raw_value = -raw_value;
+        }
+        uchar value = (uchar) (raw_value / 8.0 * max);
+
+        result[index] = value;
+    }
+
+    __constant int FAST_X[16] = {0, 1, 2, 3, 3, 3, 2, 1, 0, -1, -2, -3, -3, -3, -2, -1};
+    __constant int FAST_Y[16] = {-3, -3, -2, -1, 0, 1, 2, 3, 3, 3, 2, 1, 0, -1, -2, -3};
+
+    // Legacy FAST response of each pixel of the rect at (x, y), 0 when it is
+    // no corner. Mirrors `CpuBackend::fast_scores`.
+    __kernel void fast_scores(
+        __global int* result,
+        __global uchar* src,
+        int x,
+        int y,
+        int rect_width,
+        int width,
+        int height,
+        int threshold
+    ) {
+        int px = x + get_global_id(0);
+        int py = y + get_global_id(1);
+        int index = get_global_id(1)*rect_width + get_global_id(0);
+        result[index] = 0;
+
+        if (px < 3 || py < 3 || px + 3 >= width || py + 3 >= height) {
+            return;
+        }
 
-                result[index] = value;
+        int center = src[py*width + px];
+        int differences[16];
+        for (int i=0;i<16;i++) {
+            differences[i] = abs(src[(py + FAST_Y[i])*width + px + FAST_X[i]] - center);
+        }
+        for (int i=0;i<16;i+=4) {
+            if (differences[i] <= threshold) {
+                return;
             }
+        }
 
-            __kernel void calculate_pair(
-                __global uchar* result,
-                __global uchar* src,
-                __global uchar* a_cols,
-                __global uchar* a_rows,
-                __global uchar* b_cols,
-                __global uchar* b_rows,
-                int x,
-                int y,
-                int width,
-                int height
-            ) {
-                int index = get_global_id(0);
-                
-                int a_src_x = x+a_cols[index];
-                int a_src_y = y+a_rows[index];
-                int a_src_index = a_src_y*width + a_src_x;
-                
-                int b_src_x = x+b_cols[index];
-                int b_src_y = y+b_rows[index];
-                int b_src_index = b_src_y*width + b_src_x;
-                
-                if (a_src_x < width && a_src_y < height && b_src_x < width && b_src_y < height) {
-                    uchar a_value = src[a_src_index];
-                    uchar b_value = src[b_src_index];
-
-                    if (a_value > b_value) {
-                        result[index] = 1;
-                    } else {
-                        result[index] = 0;
-                    }
-                }
+        int run = 0;
+        int longest = 0;
+        for (int i=0;i<25;i++) {
+            if (differences[i%16] > threshold) {
+                run++;
+                longest = max(longest, run);
+            } else {
+                run = 0;
             }
-        "#;
+        }
+        if (longest < 10) {
+            return;
+        }
 
-        // (1) Define which platform and device(s) to use. Create a context,
-        // queue, and program then define some dims..
-        let platform_id = core::default_platform().unwrap();
-        let device_ids = core::get_device_ids(&platform_id, None, None).unwrap();
-        let device_id = device_ids[0];
-        let context_properties = ContextProperties::new().platform(platform_id);
-        let context = core::create_context(Some(&context_properties),
-            &[device_id], None, None).unwrap();
-        let src_cstring = CString::new(src).unwrap();
-        let program = core::create_program_with_source(&context, &[src_cstring]).unwrap();
-        core::build_program(&program, Some(&[device_id]), &CString::new("").unwrap(),
-            None, None).unwrap();
-        let queue = core::create_command_queue(&context, &device_id, None).unwrap();
+        int score = 0;
+        for (int i=0;i<16;i++) {
+            score += differences[i];
+        }
+        result[index] = score;
+    }
 
-        CL {context, program, queue}
+    // One work item per test (dimension 0) and keypoint (dimension 1).
+    // `offsets` holds ax, ay, bx, by per test, shared by all keypoints when
+    // `shared_offsets` is set and `pairs` per keypoint otherwise.
+    __kernel void calculate_pairs(
+        __global uchar* result,
+        __global uchar* src,
+        __global int* keypoints,
+        __global int* offsets,
+        int pairs,
+        int shared_offsets,
+        int width,
+        int height
+    ) {
+        int pair = get_global_id(0);
+        int keypoint = get_global_id(1);
+        int index = keypoint*pairs + pair;
+
+        int x = keypoints[keypoint*2];
+        int y = keypoints[keypoint*2 + 1];
+        int offset = (shared_offsets ? pair : index)*4;
+
+        int a_x = x + offsets[offset];
+        int a_y = y + offsets[offset + 1];
+        int b_x = x + offsets[offset + 2];
+        int b_y = y + offsets[offset + 3];
+
+        if (a_x < 0 || a_y < 0 || a_x >= width || a_y >= height ||
+            b_x < 0 || b_y < 0 || b_x >= width || b_y >= height) {
+            result[index] = 2;
+            return;
+        }
+        result[index] = src[a_y*width + a_x] > src[b_y*width + b_x] ? 1 : 0;
     }
+"#;
 
-    pub fn cl_crop(&self, data: &[u8], raw_width: i32, x: i32, y: i32, width: i32, height: i32, channels: i32)
-    -> ocl::Result<Vec<u8>>
-    {
-        let dims = [width as usize, height as usize, 1];
-        
-        let size: usize = width  as usize * height  as usize * channels as usize;
+static NEXT_THREAD_SLOT: AtomicUsize = AtomicUsize::new(0);
 
-        let mut vec = vec![0u8; size];
-        let buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_WRITE | flags::MEM_COPY_HOST_PTR, size, Some(&vec))?
-        };
+thread_local! {
+    static THREAD_SLOT: usize = NEXT_THREAD_SLOT.fetch_add(1, Ordering::Relaxed);
+}
 
-        let raw_data = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, data.len(), Some(&data))?
-        };
+// Context, program and queues are reference counted OpenCL objects, which
+// the OpenCL API allows to be used from any thread, so `CL` is `Send` and
+// `Sync` and clones share them.
+#[derive(Debug, Clone)]
+pub struct CL {
+    context: ocl::core::Context,
+    program: ocl::core::Program,
+    queues: Arc<Vec<ocl::core::CommandQueue>>,
+    device_id: ocl::core::DeviceId,
+    pool: BufferPool
+}
 
-        // (3) Create a kernel with arguments matching those in the source above:
-        let kernel = core::create_kernel(&self.program, "crop")?;
-        core::set_kernel_arg(&kernel, 0, ArgVal::mem(&buffer))?;
-        core::set_kernel_arg(&kernel, 1, ArgVal::mem(&raw_data))?;
-        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&x))?;
-        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&y))?;
-        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&width))?;
-        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&height))?;
-        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(channels as i32)))?;
-        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&(raw_width as i32)))?;
-
-        // (4) Run the kernel:
-        unsafe {
-            core::enqueue_kernel(&self.queue, &kernel, 2, None, &dims,
-                None, None::<core::Event>, None::<&mut core::Event>)?;
-        }
+impl CL {
+    // No `Default`: it would panic on machines without OpenCL.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> CL {
+        CL::try_new().unwrap()
+    }
 
-        // (5) Read results from the device into a vector:
-        unsafe {
-            core::enqueue_read_buffer(&self.queue, &buffer, true, 0, &mut vec,
-                None::<core::Event>, None::<&mut core::Event>)?;
+    // Like `new`, but reports a missing platform, device or a failed
+    // program build instead of panicking.
+    pub fn try_new() -> Result<CL, ClError> {
+        ClBuilder::new().build()
+    }
+
+    pub fn builder() -> ClBuilder {
+        ClBuilder::new()
+    }
+
+    pub(crate) fn from_device(platform_id: core::PlatformId, device_id: core::DeviceId, build_options: &str, queues: usize)
+        -> Result<CL, ClError>
+    {
+        let context_properties = ContextProperties::new().platform(platform_id);
+        let context = core::create_context(Some(&context_properties),
+            &[device_id], None, None)?;
+        let src_cstring = CString::new(PROGRAM_SOURCE)?;
+        let program = core::create_program_with_source(&context, &[src_cstring])?;
+        if let Err(e) = core::build_program(&program, Some(&[device_id]), &CString::new(build_options)?,
+            None, None)
+        {
+            let log = core::get_program_build_info(&program, device_id, ProgramBuildInfo::BuildLog)
+                .map(|log| log.to_string())
+                .unwrap_or_else(|_| e.to_string());
+            return Err(ClError::Build { device: device::device_name(device_id), log });
+        }
+        let mut command_queues = Vec::<core::CommandQueue>::with_capacity(queues.max(1));
+        for _ in 0..queues.max(1) {
+            command_queues.push(core::create_command_queue(&context, device_id, None)?);
         }
 
-        Ok(vec)
+        Ok(CL {context, program, queues: Arc::new(command_queues), device_id, pool: BufferPool::default()})
     }
 
-    pub fn cl_to_gray(
-        &self,
-        data: &[u8],
-        channels: usize
-    ) -> ocl::Result<Vec<u8>> {
-        let size: usize = data.len()/channels as usize;
-        let dims = [size, 1, 1];
+    // Each thread always gets the same queue, so the commands it enqueues
+    // stay in order; threads are spread over the pool round robin. A
+    // `ClImage` should not be shared between threads while kernels on it
+    // may still be running.
+    fn queue(&self) -> &core::CommandQueue {
+        &self.queues[self.queue_index()]
+    }
 
-        let mut vec = vec![0u8; size];
-        let buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_WRITE | flags::MEM_COPY_HOST_PTR, size, Some(&vec))?
-        };
+    fn queue_index(&self) -> usize {
+        THREAD_SLOT.with(|slot| *slot)%self.queues.len()
+    }
 
-        let raw_data = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, data.len(), Some(&data))?
-        };
+    pub fn queue_count(&self) -> usize {
+        self.queues.len()
+    }
 
-        // (3) Create a kernel with arguments matching those in the source above:
-        let kernel = core::create_kernel(&self.program, "to_gray")?;
-        core::set_kernel_arg(&kernel, 0, ArgVal::mem(&buffer))?;
-        core::set_kernel_arg(&kernel, 1, ArgVal::mem(&raw_data))?;
-        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&(channels as i32)))?;
+    pub fn device_name(&self) -> String {
+        device::device_name(self.device_id)
+    }
 
-        // (4) Run the kernel:
-        unsafe {
-            core::enqueue_kernel(&self.queue, &kernel, 2, None, &dims,
-                None, None::<core::Event>, None::<&mut core::Event>)?;
+    // A device buffer of `len` elements, from the pool when one of the same
+    // size was released on this thread's queue.
+    fn buffer<T: OclPrm>(&self, len: usize) -> ocl::Result<core::Mem> {
+        let bytes = len*size_of::<T>();
+        if let Some(mem) = self.pool.lock().unwrap().get_mut(&(self.queue_index(), bytes)).and_then(|buffers| buffers.pop()) {
+            return Ok(mem);
         }
+        let mem = unsafe {
+            core::create_buffer::<_, T>(&self.context, flags::MEM_READ_WRITE, len.max(1), None)?
+        };
+        Ok(mem)
+    }
 
-        // (5) Read results from the device into a vector:
-        unsafe {
-            core::enqueue_read_buffer(&self.queue, &buffer, true, 0, &mut vec,
-                None::<core::Event>, None::<&mut core::Event>)?;
+    pub fn empty_image<T: OclPrm>(&self, width: usize, height: usize, channels: usize) -> ocl::Result<ClImage<T>> {
+        let mem = self.buffer::<T>(width*height*channels)?;
+        Ok(ClImage::new(mem, width, height, channels, self.pool.clone(), self.queue_index()))
+    }
+
+    pub fn upload<T: OclPrm>(&self, data: &[T], width: usize, height: usize, channels: usize) -> ocl::Result<ClImage<T>> {
+        let image = self.empty_image::<T>(width, height, channels)?;
+        if !data.is_empty() {
+            unsafe {
+                core::enqueue_write_buffer(self.queue(), image.mem(), true, 0, data,
+                    None::<core::Event>, None::<&mut core::Event>)?;
+            }
         }
+        Ok(image)
+    }
 
+    pub fn download<T: OclPrm>(&self, image: &ClImage<T>) -> ocl::Result<Vec<T>> {
+        let mut vec = vec![T::default(); image.len()];
+        if !vec.is_empty() {
+            unsafe {
+                core::enqueue_read_buffer(self.queue(), image.mem(), true, 0, &mut vec,
+                    None::<core::Event>, None::<&mut core::Event>)?;
+            }
+        }
         Ok(vec)
     }
 
-    pub fn cl_normalize(&self, data: &[u8], max: f32) -> ocl::Result<Vec<f32>> {
-        let mut vec = vec![0f32; data.len()];
-        let dims = [data.len(), 1, 1];
-        
-        let result_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_WRITE | flags::MEM_COPY_HOST_PTR, data.len(), Some(&vec))?
-        };
-
-        let data_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, data.len(), Some(data))?
-        };
-
-        let kernel = core::create_kernel(&self.program, "normalize_u8")?;
-        core::set_kernel_arg(&kernel, 0, ArgVal::mem(&result_buffer))?;
-        core::set_kernel_arg(&kernel, 1, ArgVal::mem(&data_buffer))?;
-        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&max))?;
+    // Buffers of dropped images are kept for reuse; this frees them.
+    pub fn clear_pool(&self) {
+        self.pool.lock().unwrap().clear();
+    }
 
-        // Run the kernel:
-        unsafe {
-            core::enqueue_kernel(&self.queue, &kernel, 1, None, &dims,
-                None, None::<core::Event>, None::<&mut core::Event>)?;
+    fn run(&self, kernel: &core::Kernel, dims: [usize; 3]) -> ocl::Result<()> {
+        if dims.contains(&0) {
+            return Ok(());
         }
-
-        // Read results from the device into a vector:
+        let work_dim = if dims[1] > 1 { 2 } else { 1 };
         unsafe {
-            core::enqueue_read_buffer(&self.queue, &result_buffer, true, 0, &mut vec,
-                None::<core::Event>, None::<&mut core::Event>)?;
+            core::enqueue_kernel(self.queue(), kernel, work_dim, None, &dims,
+                None, None::<core::Event>, None::<&mut core::Event>)?;
         }
+        Ok(())
+    }
 
-        Ok(vec)
+    pub fn crop_image(&self, src: &ClImage<u8>, x: usize, y: usize, width: usize, height: usize) -> ocl::Result<ClImage<u8>> {
+        let result = self.empty_image::<u8>(width, height, src.channels)?;
+        let kernel = core::create_kernel(&self.program, "crop")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&(x as i32)))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(y as i32)))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(width as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(height as i32)))?;
+        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(src.channels as i32)))?;
+        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&(src.width as i32)))?;
+        self.run(&kernel, [width, height, 1])?;
+        Ok(result)
     }
 
-    pub fn cl_recover(&self, data: &[f32], max: f32) -> ocl::Result<Vec<u8>> {
-        let mut vec = vec![0u8; data.len()];
-        let dims = [data.len(), 1, 1];
-        
-        let result_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_WRITE | flags::MEM_COPY_HOST_PTR, data.len(), Some(&vec))?
-        };
+    pub fn gray_image(&self, src: &ClImage<u8>) -> ocl::Result<ClImage<u8>> {
+        let result = self.empty_image::<u8>(src.width, src.height, 1)?;
+        let kernel = core::create_kernel(&self.program, "to_gray")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&(src.channels as i32)))?;
+        self.run(&kernel, [src.width*src.height, 1, 1])?;
+        Ok(result)
+    }
 
-        let data_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, data.len(), Some(data))?
-        };
+    pub fn normalize_image(&self, src: &ClImage<u8>, max: f32) -> ocl::Result<ClImage<f32>> {
+        let result = self.empty_image::<f32>(src.width, src.height, src.channels)?;
+        let kernel = core::create_kernel(&self.program, "normalize_u8")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&max))?;
+        self.run(&kernel, [src.len(), 1, 1])?;
+        Ok(result)
+    }
 
+    pub fn recover_image(&self, src: &ClImage<f32>, max: f32) -> ocl::Result<ClImage<u8>> {
+        let result = self.empty_image::<u8>(src.width, src.height, src.channels)?;
         let kernel = core::create_kernel(&self.program, "recover_u8")?;
-        core::set_kernel_arg(&kernel, 0, ArgVal::mem(&result_buffer))?;
-        core::set_kernel_arg(&kernel, 1, ArgVal::mem(&data_buffer))?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
         core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&max))?;
+        self.run(&kernel, [src.len(), 1, 1])?;
+        Ok(result)
+    }
 
-        // Run the kernel:
-        unsafe {
-            core::enqueue_kernel(&self.queue, &kernel, 1, None, &dims,
-                None, None::<core::Event>, None::<&mut core::Event>)?;
-        }
+    // Valid region of a single channel image.
+    pub fn convolute_image(&self, src: &ClImage<f32>, convolution_kernel: &Kernel) -> ocl::Result<ClImage<f32>> {
+        let result_width  = src.width - convolution_kernel.width() + 1;
+        let result_height = src.height - convolution_kernel.height() + 1;
+        let result = self.empty_image::<f32>(result_width, result_height, 1)?;
+        let kernel_values = self.upload(&convolution_kernel.flatten(), convolution_kernel.elements(), 1, 1)?;
 
-        // Read results from the device into a vector:
-        unsafe {
-            core::enqueue_read_buffer(&self.queue, &result_buffer, true, 0, &mut vec,
-                None::<core::Event>, None::<&mut core::Event>)?;
+        let kernel = core::create_kernel(&self.program, "convolute")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::mem(kernel_values.mem()))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(src.width as i32)))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(src.height as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(convolution_kernel.width() as i32)))?;
+        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(convolution_kernel.height() as i32)))?;
+        self.run(&kernel, [result_width, result_height, 1])?;
+        Ok(result)
+    }
+
+    pub fn correlate_image(&self, src: &ClImage<f32>, pass: &Pass) -> ocl::Result<ClImage<f32>> {
+        let result = self.empty_image::<f32>(src.width, src.height, src.channels)?;
+        let kernel_values = self.upload(&pass.values, pass.values.len(), 1, 1)?;
+
+        let kernel = core::create_kernel(&self.program, "correlate")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::mem(kernel_values.mem()))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(src.width as i32)))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(src.height as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(src.channels as i32)))?;
+        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(pass.width as i32)))?;
+        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&(pass.height as i32)))?;
+        core::set_kernel_arg(&kernel, 8, ArgVal::scalar(&(pass.anchor.0 as i32)))?;
+        core::set_kernel_arg(&kernel, 9, ArgVal::scalar(&(pass.anchor.1 as i32)))?;
+        core::set_kernel_arg(&kernel, 10, ArgVal::scalar(&pass.border.code()))?;
+        core::set_kernel_arg(&kernel, 11, ArgVal::scalar(&pass.border.constant()))?;
+        self.run(&kernel, [src.width, src.height, 1])?;
+        Ok(result)
+    }
+
+    // Same size convolution of every channel, see `Backend::convolute_border`.
+    // Both passes of a rank-1 kernel stay on the device.
+    pub fn convolute_border_image(&self, src: &ClImage<f32>, convolution_kernel: &Kernel, border: BorderMode)
+        -> ocl::Result<ClImage<f32>>
+    {
+        let passes = convolution_kernel.passes(border);
+        let mut result = self.correlate_image(src, &passes[0])?;
+        for pass in &passes[1..] {
+            result = self.correlate_image(&result, pass)?;
         }
+        Ok(result)
+    }
 
-        Ok(vec)
+    // Gray, normalise, convolute and recover without leaving the device.
+    pub fn laplacian_image(&self, src: &ClImage<u8>, convolution_kernel: &Kernel) -> ocl::Result<ClImage<u8>> {
+        let gray = self.gray_image(src)?;
+        let normalized = self.normalize_image(&gray, 255.0)?;
+        let laplacian = self.convolute_image(&normalized, convolution_kernel)?;
+        self.recover_image(&laplacian, 255.0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn cl_crop(&self, data: &[u8], raw_width: i32, x: i32, y: i32, width: i32, height: i32, channels: i32)
+    -> ocl::Result<Vec<u8>>
+    {
+        let raw_height = data.len()/(raw_width as usize*channels as usize);
+        let src = self.upload(data, raw_width as usize, raw_height, channels as usize)?;
+        let result = self.crop_image(&src, x as usize, y as usize, width as usize, height as usize)?;
+        self.download(&result)
+    }
+
+    pub fn cl_to_gray(
+        &self,
+        data: &[u8],
+        channels: usize
+    ) -> ocl::Result<Vec<u8>> {
+        let src = self.upload(data, data.len()/channels, 1, channels)?;
+        self.download(&self.gray_image(&src)?)
+    }
+
+    pub fn cl_normalize(&self, data: &[u8], max: f32) -> ocl::Result<Vec<f32>> {
+        let src = self.upload(data, data.len(), 1, 1)?;
+        self.download(&self.normalize_image(&src, max)?)
+    }
+
+    pub fn cl_recover(&self, data: &[f32], max: f32) -> ocl::Result<Vec<u8>> {
+        let src = self.upload(data, data.len(), 1, 1)?;
+        self.download(&self.recover_image(&src, max)?)
     }
 
     pub fn cl_convolute(
@@ -291,47 +593,22 @@ impl CL {
         height: usize,
         convolution_kernel: &Kernel
     ) -> ocl::Result<Vec<f32>> {
-        let result_width  = width - convolution_kernel.size() + 1;
-        let result_height = height - convolution_kernel.size() + 1;
-        let dims = [result_width, result_height, 1];
-
-        let mut vec = vec![0.0; result_width*result_height];
-
-        let result_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_WRITE | flags::MEM_COPY_HOST_PTR, result_width*result_height, Some(&vec))?
-        };
-
-        let data_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, src.len(), Some(&src))?
-        };
-
-        let kernel_array = convolution_kernel.flatten();
-        let kernel_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, convolution_kernel.elements(), Some(&kernel_array))?
-        }; 
-
-        // (3) Create a kernel with arguments matching those in the source above:
-        let kernel = core::create_kernel(&self.program, "convolute")?;
-        core::set_kernel_arg(&kernel, 0, ArgVal::mem(&result_buffer))?;
-        core::set_kernel_arg(&kernel, 1, ArgVal::mem(&data_buffer))?;
-        core::set_kernel_arg(&kernel, 2, ArgVal::mem(&kernel_buffer))?;
-        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(width as i32)))?;
-        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(height as i32)))?;
-        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(convolution_kernel.size() as i32)))?;
-
-        // (4) Run the kernel:
-        unsafe {
-            core::enqueue_kernel(&self.queue, &kernel, 2, None, &dims,
-                None, None::<core::Event>, None::<&mut core::Event>)?;
-        }
-
-        // (5) Read results from the device into a vector:
-        unsafe {
-            core::enqueue_read_buffer(&self.queue, &result_buffer, true, 0, &mut vec,
-                None::<core::Event>, None::<&mut core::Event>)?;
-        }
+        let src = self.upload(src, width, height, 1)?;
+        self.download(&self.convolute_image(&src, convolution_kernel)?)
+    }
 
-        Ok(vec)
+    #[allow(clippy::too_many_arguments)]
+    pub fn cl_convolute_border(
+        &self,
+        src: &[f32],
+        width: usize,
+        height: usize,
+        channels: usize,
+        convolution_kernel: &Kernel,
+        border: BorderMode
+    ) -> ocl::Result<Vec<f32>> {
+        let src = self.upload(src, width, height, channels)?;
+        self.download(&self.convolute_border_image(&src, convolution_kernel, border)?)
     }
 
     pub fn cl_laplation(
@@ -342,10 +619,10 @@ impl CL {
         convolution_kernel: &Kernel,
         channels: usize
     ) -> ocl::Result<(usize, usize, f32, Vec<u8>)> {
-        let gray_data = self.cl_to_gray(src, channels)?;
-        let normalized_data = self.cl_normalize(&gray_data, 255.0)?;
-        let laplation_data = self.cl_convolute(&normalized_data, width, height, convolution_kernel)?;
-        let recovered_data = self.cl_recover(&laplation_data, 255.0)?;
+        let src = self.upload(src, width, height, channels)?;
+        let laplacian = self.laplacian_image(&src, convolution_kernel)?;
+        let recovered_data = self.download(&laplacian)?;
+
         let length = recovered_data.len();
         let mut total = 0f64;
         for pixel in &recovered_data {
@@ -359,14 +636,133 @@ impl CL {
         }
 
         let standard_deviation = (variance/length as f64).sqrt() as f32;
+        Ok((laplacian.width, laplacian.height, standard_deviation, recovered_data))
+    }
+
+    pub fn median_image(&self, src: &ClImage<u8>, radius: usize) -> ocl::Result<ClImage<u8>> {
+        let result = self.empty_image::<u8>(src.width, src.height, src.channels)?;
+        let kernel = core::create_kernel(&self.program, "median")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&(src.width as i32)))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(src.height as i32)))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(src.channels as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(radius as i32)))?;
+        self.run(&kernel, [src.width, src.height, 1])?;
+        Ok(result)
+    }
+
+    pub fn cl_median(&self, src: &[u8], width: usize, height: usize, channels: usize, radius: usize) -> ocl::Result<Vec<u8>> {
+        if width == 0 || height == 0 || radius == 0 {
+            return Ok(src.to_vec());
+        }
+        let src = self.upload(src, width, height, channels)?;
+        self.download(&self.median_image(&src, radius)?)
+    }
+
+    pub fn bilateral_image(&self, src: &ClImage<u8>, radius: usize, sigma_space: f32, sigma_color: f32) -> ocl::Result<ClImage<u8>> {
+        // The kernel sums into a private array of four channels.
+        if src.channels > 4 {
+            return Err(format!("The bilateral kernel handles up to 4 channels, but there are {}", src.channels).into());
+        }
+        let result = self.empty_image::<u8>(src.width, src.height, src.channels)?;
+        let kernel = core::create_kernel(&self.program, "bilateral")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&(src.width as i32)))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(src.height as i32)))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(src.channels as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(radius as i32)))?;
+        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(-0.5/(sigma_space*sigma_space).max(f32::EPSILON))))?;
+        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&(-0.5/(sigma_color*sigma_color).max(f32::EPSILON))))?;
+        self.run(&kernel, [src.width, src.height, 1])?;
+        Ok(result)
+    }
 
-        let result_width = width - convolution_kernel.size() + 1;
-        let result_height = height - convolution_kernel.size() + 1;
-        Ok((result_width, result_height, standard_deviation, recovered_data))
+    #[allow(clippy::too_many_arguments)]
+    pub fn cl_bilateral(
+        &self,
+        src: &[u8],
+        width: usize,
+        height: usize,
+        channels: usize,
+        radius: usize,
+        sigma_space: f32,
+        sigma_color: f32
+    ) -> ocl::Result<Vec<u8>> {
+        if width == 0 || height == 0 {
+            return Ok(src.to_vec());
+        }
+        let src = self.upload(src, width, height, channels)?;
+        self.download(&self.bilateral_image(&src, radius, sigma_space, sigma_color)?)
     }
 
     pub fn cl_resize() {}
 
+    // FAST responses of `rect` in a single channel image, see
+    // `Backend::fast_scores`.
+    pub fn fast_image(&self, src: &ClImage<u8>, rect: &Rect, threshold: usize) -> ocl::Result<ClImage<i32>> {
+        let (x, y, width, height) = *rect;
+        let result = self.empty_image::<i32>(width, height, 1)?;
+        let kernel = core::create_kernel(&self.program, "fast_scores")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::scalar(&(x as i32)))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::scalar(&(y as i32)))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(width as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&(src.width as i32)))?;
+        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(src.height as i32)))?;
+        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&(threshold.min(i32::MAX as usize) as i32)))?;
+        self.run(&kernel, [width, height, 1])?;
+        Ok(result)
+    }
+
+    pub fn cl_fast_scores(&self, src: &[u8], width: usize, height: usize, rect: &Rect, threshold: usize) -> ocl::Result<Vec<i32>> {
+        let src = self.upload(src, width, height, 1)?;
+        self.download(&self.fast_image(&src, rect, threshold)?)
+    }
+
+    // BRIEF tests of all keypoints in one dispatch, see
+    // `Backend::calculate_pairs`. The result has a row of `pairs` tests per
+    // keypoint.
+    pub fn pairs_image(&self, src: &ClImage<u8>, keypoints: &[(i32, i32)], offsets: &[Offsets], pairs: usize)
+        -> ocl::Result<ClImage<u8>>
+    {
+        let result = self.empty_image::<u8>(pairs, keypoints.len(), 1)?;
+        let keypoint_values: Vec<i32> = keypoints.iter().flat_map(|&(x, y)| vec![x, y]).collect();
+        let offset_values: Vec<i32> = offsets.iter().flat_map(|&(ax, ay, bx, by)| vec![ax, ay, bx, by]).collect();
+        let keypoints_buffer = self.upload(&keypoint_values, keypoint_values.len(), 1, 1)?;
+        let offsets_buffer = self.upload(&offset_values, offset_values.len(), 1, 1)?;
+
+        let kernel = core::create_kernel(&self.program, "calculate_pairs")?;
+        core::set_kernel_arg(&kernel, 0, ArgVal::mem(result.mem()))?;
+        core::set_kernel_arg(&kernel, 1, ArgVal::mem(src.mem()))?;
+        core::set_kernel_arg(&kernel, 2, ArgVal::mem(keypoints_buffer.mem()))?;
+        core::set_kernel_arg(&kernel, 3, ArgVal::mem(offsets_buffer.mem()))?;
+        core::set_kernel_arg(&kernel, 4, ArgVal::scalar(&(pairs as i32)))?;
+        core::set_kernel_arg(&kernel, 5, ArgVal::scalar(&((offsets.len() == pairs) as i32)))?;
+        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&(src.width as i32)))?;
+        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&(src.height as i32)))?;
+        self.run(&kernel, [pairs, keypoints.len(), 1])?;
+        Ok(result)
+    }
+
+    pub fn cl_calculate_pairs(
+        &self,
+        src: &[u8],
+        width: usize,
+        height: usize,
+        keypoints: &[(i32, i32)],
+        offsets: &[Offsets],
+        pairs: usize
+    ) -> ocl::Result<Vec<u8>> {
+        let now = Instant::now();
+        let src = self.upload(src, width, height, 1)?;
+        let tests = self.download(&self.pairs_image(&src, keypoints, offsets, pairs)?)?;
+        debug!("Calculate pairs of {} keypoints: {:?}", keypoints.len(), now.elapsed().as_millis());
+        Ok(tests)
+    }
+
     pub fn cl_calculate_pair(
         &self,
         src: &[u8],
@@ -381,59 +777,8 @@ impl CL {
     )
         -> ocl::Result<Vec<i32>>
     {
-        let mut vec = vec![2i32; 1024];
-        let dims = [1024, 1, 1];
-
-        let result_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_WRITE | flags::MEM_COPY_HOST_PTR, 1024, Some(&vec))?
-        };
-
-        let src_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, src.len(), Some(src))?
-        };
-        
-        let ax_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, 1024, Some(&ax))?
-        };
-
-        let ay_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, 1024, Some(&ay))?
-        };
-
-        let bx_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, 1024, Some(&bx))?
-        };
-
-        let by_buffer = unsafe {
-            core::create_buffer(&self.context, flags::MEM_READ_ONLY | flags::MEM_COPY_HOST_PTR, 1024, Some(&by))?
-        };
-
-        let kernel = core::create_kernel(&self.program, "calculate_pair")?;
-        core::set_kernel_arg(&kernel, 0, ArgVal::mem(&result_buffer))?;
-        core::set_kernel_arg(&kernel, 1, ArgVal::mem(&src_buffer))?;
-        core::set_kernel_arg(&kernel, 2, ArgVal::mem(&ax_buffer))?;
-        core::set_kernel_arg(&kernel, 3, ArgVal::mem(&ay_buffer))?;
-        core::set_kernel_arg(&kernel, 4, ArgVal::mem(&bx_buffer))?;
-        core::set_kernel_arg(&kernel, 5, ArgVal::mem(&by_buffer))?;
-        core::set_kernel_arg(&kernel, 6, ArgVal::scalar(&x))?;
-        core::set_kernel_arg(&kernel, 7, ArgVal::scalar(&y))?;
-        core::set_kernel_arg(&kernel, 8, ArgVal::scalar(&width))?;
-        core::set_kernel_arg(&kernel, 9, ArgVal::scalar(&height))?;
-        
-                // Run the kernel:
-        let now = Instant::now();
-        unsafe {
-            core::enqueue_kernel(&self.queue, &kernel, 1, None, &dims,
-                None, None::<core::Event>, None::<&mut core::Event>)?;
-        }
-
-        // Read results from the device into a vector:
-        unsafe {
-            core::enqueue_read_buffer(&self.queue, &result_buffer, true, 0, &mut vec,
-                None::<core::Event>, None::<&mut core::Event>)?;
-        }
-
-        println!("Calculate Pair: {:?}", now.elapsed().as_millis());
-        Ok(vec)
+        let offsets: Vec<Offsets> = (0..ax.len()).map(|i| (ax[i], ay[i], bx[i], by[i])).collect();
+        let tests = self.cl_calculate_pairs(src, width as usize, height as usize, &[(x, y)], &offsets, offsets.len())?;
+        Ok(tests.into_iter().map(|test| test as i32).collect())
     }
 }
\ No newline at end of file
@@ -1,20 +1,35 @@
 pub mod mat;
+pub mod backend;
+#[cfg(feature = "opencl")]
 pub mod cl;
+pub mod progress;
 
-use cl::CL;
+use backend::{Backend, run_on};
 use mat::Mat;
 use mat::pixel_description::{PixelDescription, Direction};
+use progress::{LogObserver, Observer, timed};
 use std::time::Instant;
 
 #[macro_use]
 extern crate lazy_static;
+#[macro_use]
+extern crate log;
 
 pub fn stitch_left_right(left: &Mat, right: &Mat)
     -> (Mat, f32, f32)
+{
+    stitch_left_right_with(left, right, backend::default_backend(), &LogObserver)
+}
+
+// `stitch_left_right` on an explicit backend, reporting progress and stage
+// timings to `observer`.
+pub fn stitch_left_right_with(left: &Mat, right: &Mat, backend: &dyn Backend, observer: &dyn Observer)
+    -> (Mat, f32, f32)
 {
     let total_begin = Instant::now();
-    let left_gray = left.to_gray();
-    let right_gray = right.to_gray();
+    let (left_gray, right_gray) = timed(observer, "stitch.gray", || {
+        (run_on(backend, |b| left.to_gray_with(b)), run_on(backend, |b| right.to_gray_with(b)))
+    });
     // 433ms
     fn gen_masks(src: &Mat, width: usize, height: usize)
     -> Vec<((usize, usize, usize, usize), (usize, usize, usize, usize))>
@@ -36,15 +51,19 @@ pub fn stitch_left_right(left: &Mat, right: &Mat)
     let mut match_points = Vec::<(PixelDescription, PixelDescription)>::new();
     let mask_pairs = gen_masks(&left_gray, 150, 150);
 
-    for mask_pair in mask_pairs {
-        let left_descriptions = left_gray.fast_search_features(10, &mask_pair.0, Direction::Horizontal);
-        let right_descriptions = right_gray.fast_search_features(10, &mask_pair.1, Direction::Horizontal);
+    let total = mask_pairs.len();
+    for (i, mask_pair) in mask_pairs.iter().enumerate() {
+        let left_descriptions = left_gray.fast_search_features_with(backend, 10, &mask_pair.0, Direction::Horizontal, observer);
+        let right_descriptions = right_gray.fast_search_features_with(backend, 10, &mask_pair.1, Direction::Horizontal, observer);
 
-        let points = &PixelDescription::match_points(&left_descriptions, &right_descriptions, 900);
+        let points = &timed(observer, "stitch.match", || {
+            PixelDescription::match_points(&left_descriptions, &right_descriptions, 900)
+        });
         match_points.extend_from_slice(points);
+        observer.progress("stitch", i + 1, total);
     }
 
-    println!("Pairs: {:?}", match_points.len());
+    debug!("Pairs: {:?}", match_points.len());
 
     // 922ms
     let mut move_vector = Mat::avg_mapping_vector(&match_points);
@@ -59,29 +78,36 @@ pub fn stitch_left_right(left: &Mat, right: &Mat)
 
     let shared_section = transition_section(&left, move_vector);
 
-    println!("{:?}", move_vector);
+    debug!("Move vector: {:?}", move_vector);
     dist.merge(left, 0, 0);
-    println!("{:?}", shared_section);
-    println!("===================================================================================================");
-    let left_shared_mat = dist.crop(shared_section.0, shared_section.1, shared_section.2, shared_section.3);
+    debug!("Shared section: {:?}", shared_section);
+    let left_shared_mat = run_on(backend, |b| dist.crop_with(b, shared_section.0, shared_section.1, shared_section.2, shared_section.3));
     Mat::move_mat(&mut dist, &right, move_vector);
     // Mat::move_mat_by_multi_points(&mut dist, &right, &match_points, move_vector);
-    let right_shared_mat = dist.crop(shared_section.0, shared_section.1, shared_section.2, shared_section.3);
+    let right_shared_mat = run_on(backend, |b| dist.crop_with(b, shared_section.0, shared_section.1, shared_section.2, shared_section.3));
 
-    let shared_mat = fuse(&left_shared_mat, &right_shared_mat, Direction::Horizontal);
-    let total_begin = total_begin.elapsed().as_millis();
+    let shared_mat = timed(observer, "stitch.fuse", || fuse(&left_shared_mat, &right_shared_mat, Direction::Horizontal));
     // shared_mat.save_as_png("shared_mat_1.png");
-    println!("Spend ms on STITCHING:{}", total_begin);
+    observer.stage("stitch", total_begin.elapsed());
     dist.merge(&shared_mat, shared_section.0 as usize, shared_section.1 as usize);
     (dist, move_vector.0, move_vector.1)
 }
 
 pub fn stitch_top_bottom(top: &Mat, bottom: &Mat)
     -> (Mat, f32, f32)
+{
+    stitch_top_bottom_with(top, bottom, backend::default_backend(), &LogObserver)
+}
+
+// `stitch_top_bottom` on an explicit backend, reporting progress and stage
+// timings to `observer`.
+pub fn stitch_top_bottom_with(top: &Mat, bottom: &Mat, backend: &dyn Backend, observer: &dyn Observer)
+    -> (Mat, f32, f32)
 {
     let total_begin = Instant::now();
-    let top_gray = top.to_gray();
-    let bottom_gray = bottom.to_gray();
+    let (top_gray, bottom_gray) = timed(observer, "stitch.gray", || {
+        (run_on(backend, |b| top.to_gray_with(b)), run_on(backend, |b| bottom.to_gray_with(b)))
+    });
 
     fn gen_masks(src: &Mat, width: usize, height: usize)
     -> Vec<((usize, usize, usize, usize), (usize, usize, usize, usize))>
@@ -102,22 +128,24 @@ pub fn stitch_top_bottom(top: &Mat, bottom: &Mat)
 
     let mut match_points = Vec::<(PixelDescription, PixelDescription)>::new();
     let mask_pairs = gen_masks(&top_gray, 400, 162);
-    let total_begin = total_begin.elapsed().as_millis();
-    for mask_pair in mask_pairs {
-        println!("Mask X: {:?}", (mask_pair.0).0);
-        let top_descriptions = top_gray.fast_search_features(10, &mask_pair.0, Direction::Vertical);
-        let bottom_descriptions = bottom_gray.fast_search_features(10, &mask_pair.1, Direction::Vertical);
-
-        let points = &PixelDescription::match_points(&top_descriptions, &bottom_descriptions, 900);
-        println!("Pairs: {:?}", points.len());
-        println!("Mask: ====================================================");
+    let total = mask_pairs.len();
+    for (i, mask_pair) in mask_pairs.iter().enumerate() {
+        trace!("Mask X: {:?}", (mask_pair.0).0);
+        let top_descriptions = top_gray.fast_search_features_with(backend, 10, &mask_pair.0, Direction::Vertical, observer);
+        let bottom_descriptions = bottom_gray.fast_search_features_with(backend, 10, &mask_pair.1, Direction::Vertical, observer);
+
+        let points = &timed(observer, "stitch.match", || {
+            PixelDescription::match_points(&top_descriptions, &bottom_descriptions, 900)
+        });
+        trace!("Pairs: {:?}", points.len());
         match_points.extend_from_slice(points);
+        observer.progress("stitch", i + 1, total);
     }
 
     let mut move_vector = Mat::avg_mapping_vector(&match_points);
     let mut multi_points = true;
     if move_vector.0.is_nan() || move_vector.1.is_nan() {
-        println!("强行来个位置");
+        warn!("No matching points between top and bottom, falling back to a fixed offset");
         move_vector = (0f32, (top.rows - 156) as f32);
         multi_points = false;
     }
@@ -126,22 +154,21 @@ pub fn stitch_top_bottom(top: &Mat, bottom: &Mat)
     let shared_section = transition_section(&top, move_vector);
 
     Mat::move_mat(&mut dist, &top, (0., 0.));
-    println!("{:?}", shared_section);
-    println!("===================================================================================================");
-    let top_shared_mat = dist.crop(shared_section.0, shared_section.1, shared_section.2, shared_section.3);
+    debug!("Shared section: {:?}", shared_section);
+    let top_shared_mat = run_on(backend, |b| dist.crop_with(b, shared_section.0, shared_section.1, shared_section.2, shared_section.3));
     
     // if multi_points {
     //     Mat::move_mat_by_multi_points(&mut dist, &bottom, move_vector, &match_points);
     // } else {
         Mat::move_mat(&mut dist, &bottom, move_vector);
-        let bottom_shared_mat = dist.crop(shared_section.0, shared_section.1, shared_section.2, shared_section.3);
-        let shared_mat = fuse(&top_shared_mat, &bottom_shared_mat, Direction::Vertical);
+        let bottom_shared_mat = run_on(backend, |b| dist.crop_with(b, shared_section.0, shared_section.1, shared_section.2, shared_section.3));
+        let shared_mat = timed(observer, "stitch.fuse", || fuse(&top_shared_mat, &bottom_shared_mat, Direction::Vertical));
         dist.merge(&shared_mat, shared_section.0 as usize, shared_section.1 as usize);
     // }
     // Mat::move_mat(&mut dist, &bottom, move_vector);
     // shared_mat.save_as_png("shared_mat_2.png");
 
-    println!("Spend ms on STITCHING:{}", total_begin);
+    observer.stage("stitch", total_begin.elapsed());
     // for pair in match_points {
     //     // println!("{:?}, ", pair.0.coordinate.0);
     //     dist.draw_point(pair.0.coordinate, vec!(255u8, 0u8, 0u8));
@@ -157,7 +184,7 @@ fn fuse(a_image: &Mat, b_image: &Mat, direction: Direction) -> Mat {
             let mut new_vec = Vec::<u8>::new();
             for i in 0..a_image.bytes_per_pixel {
                 let factor = match direction {
-                    Direction::Horizontal => {
+                    Direction::Horizontal | Direction::Oriented => {
                         1.0 - x as f32/a_image.cols as f32
                     },
                     Direction::Vertical => {
@@ -0,0 +1,261 @@
+use super::Mat;
+use super::corners::Rect;
+use super::float_mat::FloatMat;
+use super::kernels::{BorderMode, Kernel};
+use super::sharpness::{SharpnessMap, SharpnessMethod};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlurKind {
+    Sharp,
+    // Too little contrast to judge, e.g. sky or a wall.
+    Flat,
+    // Blurred evenly in all directions.
+    Defocus,
+    // Blurred along one direction, `angle` in degrees counter-clockwise from
+    // the x axis, in 0..180 like `Kernel::motion_blur`.
+    Motion { angle: f32 },
+}
+
+impl BlurKind {
+    pub fn is_blurred(&self) -> bool {
+        matches!(self, BlurKind::Defocus | BlurKind::Motion { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlurThreshold {
+    // Tiles scoring below this are blurred.
+    Absolute(f32),
+    // Tiles scoring below this fraction of the 90th percentile of the tiles
+    // that are not `Flat` are blurred, so a few very crisp tiles do not
+    // condemn the rest.
+    Relative(f32),
+}
+
+#[derive(Debug, Clone)]
+pub struct BlurOptions {
+    pub method: SharpnessMethod,
+    // Tile width and height in pixels.
+    pub tile_size: usize,
+    pub threshold: BlurThreshold,
+    // Tiles whose gray levels have a standard deviation below this are
+    // `Flat`. Unlike gradients, the spread of the levels barely drops when a
+    // tile is blurred, so defocused detail is still judged.
+    pub min_contrast: f32,
+    // Coherence of the fine detail gradients, 0 for isotropic to 1 for a
+    // single direction, above which a blurred tile counts as motion blur.
+    // Texture running in one direction, like furrows, looks the same.
+    pub min_coherence: f32,
+}
+
+impl Default for BlurOptions {
+    fn default() -> BlurOptions {
+        BlurOptions {
+            method: SharpnessMethod::VarianceOfLaplacian,
+            tile_size: 64,
+            threshold: BlurThreshold::Relative(0.2),
+            min_contrast: 8.0,
+            min_coherence: 0.6,
+        }
+    }
+}
+
+// Connected blurred tiles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlurRegion {
+    pub rect: Rect,
+    pub tiles: usize,
+    // Mean sharpness of the tiles.
+    pub sharpness: f32,
+    // `Motion` when most tiles are, with their mean angle, `Defocus` otherwise.
+    pub kind: BlurKind,
+}
+
+pub struct BlurReport {
+    pub map: SharpnessMap,
+    // Classification of every tile, row by row like `map.values`.
+    pub kinds: Vec<BlurKind>,
+    // Gray image of the input size, brighter is blurrier. Flat tiles, which
+    // cannot be judged, are mid gray.
+    pub heat_map: Mat,
+    pub regions: Vec<BlurRegion>,
+}
+
+// Sobel responses of an image.
+struct Gradients {
+    x: Vec<f32>,
+    y: Vec<f32>,
+}
+
+impl Gradients {
+    fn of(image: &FloatMat) -> Gradients {
+        Gradients {
+            x: image.convolute(&Kernel::sobel_x(), BorderMode::Reflect).values,
+            y: image.convolute(&Kernel::sobel_y(), BorderMode::Reflect).values,
+        }
+    }
+
+    // Dominant orientation in degrees and coherence inside `rect`, from the
+    // structure tensor.
+    fn orientation(&self, cols: usize, rect: Rect) -> (f32, f32) {
+        let (x, y, width, height) = rect;
+        let (mut jxx, mut jyy, mut jxy) = (0f64, 0f64, 0f64);
+        for row in y..(y + height) {
+            for col in x..(x + width) {
+                let i = row*cols + col;
+                let (dx, dy) = (self.x[i] as f64, self.y[i] as f64);
+                jxx += dx*dx;
+                jyy += dy*dy;
+                jxy += dx*dy;
+            }
+        }
+        let trace = jxx + jyy;
+        let coherence = if trace > 0.0 { ((jxx - jyy).powi(2) + 4.0*jxy*jxy).sqrt()/trace } else { 0.0 };
+        // Image rows grow downwards, so the angle flips to be counter-clockwise.
+        let angle = -0.5*(2.0*jxy).atan2(jxx - jyy);
+        (angle.to_degrees() as f32, coherence as f32)
+    }
+}
+
+// Standard deviation of the values inside `rect`.
+fn deviation(image: &FloatMat, rect: Rect) -> f32 {
+    let (x, y, width, height) = rect;
+    let count = (width*height).max(1) as f64;
+    let values = || (y..(y + height)).flat_map(move |row| (x..(x + width)).map(move |col| image.get(col, row, 0) as f64));
+    let mean = values().sum::<f64>()/count;
+    (values().map(|v| (v - mean).powi(2)).sum::<f64>()/count).sqrt() as f32
+}
+
+// Angle in 0..180.
+fn axial(angle: f32) -> f32 {
+    angle.rem_euclid(180.0)
+}
+
+impl Mat {
+    // Tiles the image, scores every tile and reports the blurred ones. Motion
+    // blur wipes out the detail gradients along the motion and keeps the ones
+    // across it, so blurred tiles with coherent detail gradients are `Motion`
+    // and the blur runs perpendicular to them.
+    pub fn detect_blur(&self, options: &BlurOptions) -> BlurReport {
+        let tile_size = options.tile_size.max(1);
+        let map = self.sharpness_map(options.method, self.cols/tile_size, self.rows/tile_size);
+        let gray = self.gray().to_float();
+        // Fine detail, where motion blur leaves the strongest directional
+        // trace and most natural texture is isotropic.
+        let smooth = gray.convolute(&Kernel::gaussian(1.0), BorderMode::Reflect);
+        let detail = gray.values.iter().zip(smooth.values.iter()).map(|(value, smooth)| value - smooth).collect();
+        let detail_gradients = Gradients::of(&FloatMat::new(gray.cols, gray.rows, 1, detail));
+
+        let flat: Vec<bool> = (0..map.rows).flat_map(|row| (0..map.cols).map(move |col| (col, row))).map(|(col, row)| {
+            deviation(&gray, map.tile_rect(col, row, self.cols, self.rows)) < options.min_contrast
+        }).collect();
+        let threshold = match options.threshold {
+            BlurThreshold::Absolute(value) => value,
+            BlurThreshold::Relative(fraction) => {
+                let mut scores: Vec<f32> = map.values.iter().zip(flat.iter()).filter(|(_, flat)| !**flat).map(|(score, _)| *score).collect();
+                scores.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                fraction*scores.get(scores.len()*9/10).cloned().unwrap_or(0.0)
+            }
+        };
+        let kinds: Vec<BlurKind> = (0..map.rows*map.cols).map(|tile| {
+            if flat[tile] {
+                return BlurKind::Flat;
+            }
+            if map.values[tile] >= threshold {
+                return BlurKind::Sharp;
+            }
+            let rect = map.tile_rect(tile%map.cols, tile/map.cols, self.cols, self.rows);
+            let (angle, coherence) = detail_gradients.orientation(self.cols, rect);
+            if coherence >= options.min_coherence {
+                BlurKind::Motion { angle: axial(angle + 90.0) }
+            } else {
+                BlurKind::Defocus
+            }
+        }).collect();
+
+        let heat_map = heat_map(self, &map, &kinds);
+        let regions = regions(self, &map, &kinds);
+        debug!("{} of {} tiles blurred in {} regions", kinds.iter().filter(|kind| kind.is_blurred()).count(), kinds.len(), regions.len());
+        BlurReport { map, kinds, heat_map, regions }
+    }
+}
+
+fn heat_map(image: &Mat, map: &SharpnessMap, kinds: &[BlurKind]) -> Mat {
+    let max = map.max();
+    let mut pixels = vec![0u8; image.cols*image.rows];
+    for row in 0..map.rows {
+        for col in 0..map.cols {
+            let tile = row*map.cols + col;
+            let value = if kinds[tile] == BlurKind::Flat || max <= 0.0 {
+                128
+            } else {
+                (255.0*(1.0 - map.values[tile]/max)).round().clamp(0.0, 255.0) as u8
+            };
+            let (x, y, width, height) = map.tile_rect(col, row, image.cols, image.rows);
+            for line in y..(y + height) {
+                for pixel in &mut pixels[(line*image.cols + x)..(line*image.cols + x + width)] {
+                    *pixel = value;
+                }
+            }
+        }
+    }
+    Mat::load_from_vec(pixels, image.cols, image.rows, 1)
+}
+
+// Groups 4-connected blurred tiles.
+fn regions(image: &Mat, map: &SharpnessMap, kinds: &[BlurKind]) -> Vec<BlurRegion> {
+    let mut visited = vec![false; kinds.len()];
+    let mut regions = Vec::new();
+    for start in 0..kinds.len() {
+        if visited[start] || !kinds[start].is_blurred() {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![start];
+        let mut tiles = Vec::new();
+        while let Some(tile) = stack.pop() {
+            tiles.push(tile);
+            let (col, row) = (tile%map.cols, tile/map.cols);
+            let mut neighbours = Vec::with_capacity(4);
+            if col > 0 { neighbours.push(tile - 1); }
+            if col + 1 < map.cols { neighbours.push(tile + 1); }
+            if row > 0 { neighbours.push(tile - map.cols); }
+            if row + 1 < map.rows { neighbours.push(tile + map.cols); }
+            for neighbour in neighbours {
+                if !visited[neighbour] && kinds[neighbour].is_blurred() {
+                    visited[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
+        }
+
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+        let (mut motion, mut sin, mut cos) = (0, 0f32, 0f32);
+        for tile in tiles.iter() {
+            let (x, y, width, height) = map.tile_rect(tile%map.cols, tile/map.cols, image.cols, image.rows);
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + width);
+            bottom = bottom.max(y + height);
+            if let BlurKind::Motion { angle } = kinds[*tile] {
+                // Doubled angles, so 1 and 179 degrees average to 0.
+                motion += 1;
+                let (s, c) = (2.0*angle).to_radians().sin_cos();
+                sin += s;
+                cos += c;
+            }
+        }
+        let kind = if 2*motion > tiles.len() {
+            BlurKind::Motion { angle: axial(0.5*sin.atan2(cos).to_degrees()) }
+        } else {
+            BlurKind::Defocus
+        };
+        regions.push(BlurRegion {
+            rect: (left, top, right - left, bottom - top),
+            tiles: tiles.len(),
+            sharpness: tiles.iter().map(|tile| map.values[*tile]).sum::<f32>()/tiles.len() as f32,
+            kind,
+        });
+    }
+    regions
+}
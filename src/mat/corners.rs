@@ -0,0 +1,246 @@
+use super::Mat;
+use super::nms::{self, NmsOptions};
+use super::pixel_description::{PixelDescription, Direction};
+
+pub type Rect = (usize, usize, usize, usize);
+
+// Produces candidate keypoints inside `mask`, before non maximum suppression
+// and descriptor calculation.
+pub trait Detector {
+    fn detect(&self, src: &Mat, mask: &Rect) -> Vec<PixelDescription>;
+}
+
+// Greater score is a stronger corner. Used to rank candidates of any detector.
+pub trait CornerScore {
+    fn score(&self, src: &Mat, coordinate: (usize, usize)) -> f32;
+}
+
+// The segment test of `PixelDescription::load_as_fast`, scored by the sum of
+// absolute differences on the circle.
+pub struct SegmentTest {
+    pub threshold: usize,
+}
+
+impl Detector for SegmentTest {
+    fn detect(&self, src: &Mat, mask: &Rect) -> Vec<PixelDescription> {
+        let mut descriptions = Vec::<PixelDescription>::new();
+        for y in (mask.1)..(mask.1+mask.3) {
+            for x in (mask.0)..(mask.0+mask.2) {
+                let (result, description) = PixelDescription::load_as_fast((x, y), src, self.threshold, &Direction::Horizontal);
+                if result {
+                    descriptions.push(description);
+                }
+            }
+        }
+        descriptions
+    }
+}
+
+// Sobel derivatives of the first channel over a region, scaled to [-1, 1].
+// Pixels outside the image replicate the border.
+pub struct Gradients {
+    pub region: Rect,
+    pub dx: Vec<f32>,
+    pub dy: Vec<f32>,
+}
+
+impl Gradients {
+    pub fn new(src: &Mat, region: &Rect) -> Gradients {
+        let value = |x: i32, y: i32| {
+            let x = x.clamp(0, src.cols as i32 - 1) as usize;
+            let y = y.clamp(0, src.rows as i32 - 1) as usize;
+            src.pixels[(y*src.cols + x)*src.bytes_per_pixel] as f32
+        };
+
+        let mut dx = Vec::<f32>::with_capacity(region.2*region.3);
+        let mut dy = Vec::<f32>::with_capacity(region.2*region.3);
+        for y in (region.1)..(region.1+region.3) {
+            for x in (region.0)..(region.0+region.2) {
+                let (x, y) = (x as i32, y as i32);
+                let gx = (value(x+1, y-1) + 2.0*value(x+1, y) + value(x+1, y+1))
+                    - (value(x-1, y-1) + 2.0*value(x-1, y) + value(x-1, y+1));
+                let gy = (value(x-1, y+1) + 2.0*value(x, y+1) + value(x+1, y+1))
+                    - (value(x-1, y-1) + 2.0*value(x, y-1) + value(x+1, y-1));
+                dx.push(gx/1020.0);
+                dy.push(gy/1020.0);
+            }
+        }
+        Gradients { region: *region, dx, dy }
+    }
+
+    // Sums of Ix², IxIy and Iy² over the (2r+1)² window centred on (x, y),
+    // given in image coordinates. The window is clipped to the region.
+    pub fn structure_tensor(&self, x: usize, y: usize, radius: usize) -> (f32, f32, f32) {
+        let (rx, ry, rw, rh) = self.region;
+        let x0 = x.saturating_sub(radius).max(rx);
+        let y0 = y.saturating_sub(radius).max(ry);
+        let x1 = (x + radius).min(rx + rw - 1);
+        let y1 = (y + radius).min(ry + rh - 1);
+
+        let (mut a, mut b, mut c) = (0f32, 0f32, 0f32);
+        for yy in y0..(y1 + 1) {
+            for xx in x0..(x1 + 1) {
+                let index = (yy - ry)*rw + (xx - rx);
+                let (gx, gy) = (self.dx[index], self.dy[index]);
+                a += gx*gx;
+                b += gx*gy;
+                c += gy*gy;
+            }
+        }
+        (a, b, c)
+    }
+}
+
+fn harris_response(tensor: (f32, f32, f32), k: f32) -> f32 {
+    let (a, b, c) = tensor;
+    (a*c - b*b) - k*(a + c)*(a + c)
+}
+
+fn min_eigenvalue(tensor: (f32, f32, f32)) -> f32 {
+    let (a, b, c) = tensor;
+    (a + c)/2.0 - (((a - c)/2.0).powi(2) + b*b).sqrt()
+}
+
+// Region covering `mask` plus the margin a window of `radius` needs.
+fn padded_region(src: &Mat, mask: &Rect, radius: usize) -> Rect {
+    let x = mask.0.saturating_sub(radius);
+    let y = mask.1.saturating_sub(radius);
+    let w = (mask.0 + mask.2 + radius).min(src.cols) - x;
+    let h = (mask.1 + mask.3 + radius).min(src.rows) - y;
+    (x, y, w, h)
+}
+
+// Keeps the pixels of `mask` whose response reaches `quality` times the best
+// response and is a maximum of its 3x3 neighbourhood.
+fn detect_by_response(src: &Mat, mask: &Rect, radius: usize, quality: f32, response: &dyn Fn((f32, f32, f32)) -> f32)
+    -> Vec<PixelDescription>
+{
+    if mask.2 == 0 || mask.3 == 0 {
+        return Vec::new();
+    }
+    let gradients = Gradients::new(src, &padded_region(src, mask, radius));
+    let (mx, my, mw, mh) = *mask;
+
+    let mut responses = vec![0f32; mw*mh];
+    let mut max_response = 0f32;
+    for y in 0..mh {
+        for x in 0..mw {
+            let value = response(gradients.structure_tensor(mx + x, my + y, radius));
+            responses[y*mw + x] = value;
+            max_response = max_response.max(value);
+        }
+    }
+
+    let mut descriptions = Vec::<PixelDescription>::new();
+    if max_response <= 0.0 {
+        return descriptions;
+    }
+    let threshold = max_response*quality;
+
+    for y in 0..mh {
+        for x in 0..mw {
+            let value = responses[y*mw + x];
+            if value < threshold {
+                continue;
+            }
+            let mut is_maximum = true;
+            for ny in y.saturating_sub(1)..(y + 2).min(mh) {
+                for nx in x.saturating_sub(1)..(x + 2).min(mw) {
+                    if (nx, ny) != (x, y) && responses[ny*mw + nx] > value {
+                        is_maximum = false;
+                    }
+                }
+            }
+            if is_maximum {
+                let coordinate = (mx + x, my + y);
+                let mut description = PixelDescription::new();
+                description.coordinate = coordinate;
+                description.value = src.get_pixel_by_xy(coordinate.0, coordinate.1)[0];
+                description.response = value;
+                description.removed = false;
+                descriptions.push(description);
+            }
+        }
+    }
+    descriptions
+}
+
+pub struct HarrisDetector {
+    pub k: f32,
+    // Half size of the summation window, 1 is a 3x3 window.
+    pub window_radius: usize,
+    // Minimum response relative to the strongest one in the mask.
+    pub quality: f32,
+}
+
+impl Default for HarrisDetector {
+    fn default() -> HarrisDetector {
+        HarrisDetector { k: 0.04, window_radius: 1, quality: 0.01 }
+    }
+}
+
+impl Detector for HarrisDetector {
+    fn detect(&self, src: &Mat, mask: &Rect) -> Vec<PixelDescription> {
+        let k = self.k;
+        detect_by_response(src, mask, self.window_radius, self.quality, &|tensor| harris_response(tensor, k))
+    }
+}
+
+impl CornerScore for HarrisDetector {
+    fn score(&self, src: &Mat, coordinate: (usize, usize)) -> f32 {
+        let region = padded_region(src, &(coordinate.0, coordinate.1, 1, 1), self.window_radius);
+        let tensor = Gradients::new(src, &region).structure_tensor(coordinate.0, coordinate.1, self.window_radius);
+        harris_response(tensor, self.k)
+    }
+}
+
+pub struct ShiTomasiDetector {
+    pub window_radius: usize,
+    pub quality: f32,
+}
+
+impl Default for ShiTomasiDetector {
+    fn default() -> ShiTomasiDetector {
+        ShiTomasiDetector { window_radius: 1, quality: 0.01 }
+    }
+}
+
+impl Detector for ShiTomasiDetector {
+    fn detect(&self, src: &Mat, mask: &Rect) -> Vec<PixelDescription> {
+        detect_by_response(src, mask, self.window_radius, self.quality, &min_eigenvalue)
+    }
+}
+
+impl CornerScore for ShiTomasiDetector {
+    fn score(&self, src: &Mat, coordinate: (usize, usize)) -> f32 {
+        let region = padded_region(src, &(coordinate.0, coordinate.1, 1, 1), self.window_radius);
+        let tensor = Gradients::new(src, &region).structure_tensor(coordinate.0, coordinate.1, self.window_radius);
+        min_eigenvalue(tensor)
+    }
+}
+
+impl Mat {
+    // Detection pipeline shared by all detectors: candidates, optional
+    // rescoring, non maximum suppression (with top-N selection) and descriptors.
+    pub fn detect_features(
+        &self,
+        detector: &dyn Detector,
+        mask: &Rect,
+        direction: Direction,
+        score: Option<&dyn CornerScore>,
+        options: &NmsOptions
+    ) -> Vec<PixelDescription> {
+        let mut descriptions = detector.detect(self, mask);
+
+        if let Some(score) = score {
+            for description in descriptions.iter_mut() {
+                description.response = score.score(self, description.coordinate);
+            }
+        }
+
+        descriptions = nms::suppress(&descriptions, options);
+
+        self.describe(&mut descriptions, &direction);
+        descriptions
+    }
+}
@@ -0,0 +1,122 @@
+use std::convert::TryFrom;
+
+use serde::{Serialize, Deserialize};
+
+use super::brief_pattern::PAIRS;
+
+pub const DESCRIPTOR_WORDS: usize = PAIRS/64;
+
+// BRIEF test results packed 64 per word. Tests whose samples fall outside
+// the image are skipped, so `len` may be shorter than `PAIRS`; bits past
+// `len` are always zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "HexDescriptor", into = "HexDescriptor")]
+pub struct BinaryDescriptor {
+    pub bits: [u64; DESCRIPTOR_WORDS],
+    pub len: usize,
+}
+
+impl BinaryDescriptor {
+    pub fn new() -> BinaryDescriptor {
+        BinaryDescriptor { bits: [0u64; DESCRIPTOR_WORDS], len: 0 }
+    }
+
+    // Conversion from the one-byte-per-test representation (0 or 1).
+    pub fn from_pairs(pairs: &[u8]) -> BinaryDescriptor {
+        let mut descriptor = BinaryDescriptor::new();
+        for pair in pairs {
+            descriptor.push(*pair != 0);
+        }
+        descriptor
+    }
+
+    pub fn to_pairs(&self) -> Vec<u8> {
+        (0..self.len).map(|i| self.get(i) as u8).collect()
+    }
+
+    pub fn push(&mut self, bit: bool) {
+        if self.len >= PAIRS {
+            panic!("A descriptor holds at most {} tests", PAIRS);
+        }
+        if bit {
+            self.bits[self.len/64] |= 1u64 << (self.len%64);
+        }
+        self.len += 1;
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        index < self.len && (self.bits[index/64] >> (index%64)) & 1 == 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Words holding the first `len` bits, the rest are always zero.
+    pub fn words(&self) -> &[u64] {
+        &self.bits[..(self.len + 63)/64]
+    }
+
+    pub fn from_words(words: &[u64], len: usize) -> Result<BinaryDescriptor, String> {
+        if len > PAIRS || words.len() != (len + 63)/64 {
+            return Err(format!("{} words can not hold a descriptor of {} tests", words.len(), len));
+        }
+        let mut descriptor = BinaryDescriptor::new();
+        descriptor.bits[..words.len()].copy_from_slice(words);
+        descriptor.len = len;
+        if len%64 != 0 && descriptor.bits[len/64] >> (len%64) != 0 {
+            return Err(format!("Descriptor has bits set past its {} tests", len));
+        }
+        Ok(descriptor)
+    }
+
+    pub fn hamming(&self, other: &BinaryDescriptor) -> u32 {
+        let words = (self.len.max(other.len) + 63)/64;
+        let mut distance = 0;
+        for i in 0..words {
+            distance += (self.bits[i] ^ other.bits[i]).count_ones();
+        }
+        distance
+    }
+}
+
+impl Default for BinaryDescriptor {
+    fn default() -> BinaryDescriptor {
+        BinaryDescriptor::new()
+    }
+}
+
+// Serialised form: the used words as 16 hex digits each, so descriptors
+// stay readable and exact in JSON.
+#[derive(Serialize, Deserialize)]
+struct HexDescriptor {
+    len: usize,
+    bits: String,
+}
+
+impl From<BinaryDescriptor> for HexDescriptor {
+    fn from(descriptor: BinaryDescriptor) -> HexDescriptor {
+        let bits = descriptor.words().iter().map(|word| format!("{:016x}", word)).collect();
+        HexDescriptor { len: descriptor.len, bits }
+    }
+}
+
+impl TryFrom<HexDescriptor> for BinaryDescriptor {
+    type Error = String;
+
+    fn try_from(hex: HexDescriptor) -> Result<BinaryDescriptor, String> {
+        if hex.bits.len()%16 != 0 || !hex.bits.is_ascii() {
+            return Err(format!("Descriptor bits should be 16 hex digits per word, got {:?}", hex.bits));
+        }
+        let mut words = Vec::<u64>::with_capacity(hex.bits.len()/16);
+        for i in (0..hex.bits.len()).step_by(16) {
+            let word = u64::from_str_radix(&hex.bits[i..(i + 16)], 16).map_err(|e| e.to_string())?;
+            words.push(word);
+        }
+        BinaryDescriptor::from_words(&words, hex.len)
+    }
+}
@@ -0,0 +1,159 @@
+use super::Mat;
+use super::corners::{CornerScore, Detector, Rect};
+use super::pixel_description::PixelDescription;
+
+// Bresenham circle of radius 3, clockwise from the top.
+pub const CIRCLE: [(i32, i32); 16] = [
+    (0, -3), (1, -3), (2, -2), (3, -1),
+    (3, 0), (3, 1), (2, 2), (1, 3),
+    (0, 3), (-1, 3), (-2, 2), (-3, 1),
+    (-3, 0), (-3, -1), (-2, -2), (-1, -3)
+];
+
+// FAST segment test: a pixel is a corner when `arc_length` contiguous circle
+// pixels are all brighter than centre + threshold, or all darker than
+// centre - threshold. The score is the largest threshold for which the pixel
+// is still a corner.
+#[derive(Debug, Clone, Copy)]
+pub struct FastDetector {
+    pub threshold: u8,
+    pub arc_length: usize,
+}
+
+impl Default for FastDetector {
+    fn default() -> FastDetector {
+        FastDetector { threshold: 20, arc_length: 9 }
+    }
+}
+
+impl FastDetector {
+    pub fn new(threshold: u8, arc_length: usize) -> FastDetector {
+        if !(9..=12).contains(&arc_length) {
+            panic!("FAST arc length should be between 9 and 12, but it is {}", arc_length);
+        }
+        FastDetector { threshold, arc_length }
+    }
+
+    // Circle values, or None if the circle leaves the image.
+    fn circle(src: &Mat, coordinate: (usize, usize)) -> Option<[i16; 16]> {
+        let (x, y) = coordinate;
+        if x < 3 || y < 3 || x + 3 >= src.cols || y + 3 >= src.rows {
+            return None;
+        }
+        let mut values = [0i16; 16];
+        for (i, (dx, dy)) in CIRCLE.iter().enumerate() {
+            let index = ((y as i32 + dy) as usize)*src.cols + (x as i32 + dx) as usize;
+            values[i] = src.pixels[index*src.bytes_per_pixel] as i16;
+        }
+        Some(values)
+    }
+
+    fn passes(&self, center: i16, circle: &[i16; 16], threshold: i16) -> bool {
+        // Every arc of n >= 9 pixels covers at least n/4 of the compass
+        // points, which rejects most pixels after four reads.
+        let required = (self.arc_length/4) as i32;
+        let mut brighter = 0;
+        let mut darker = 0;
+        for i in (0..16).step_by(4) {
+            if circle[i] > center + threshold {
+                brighter += 1;
+            } else if circle[i] < center - threshold {
+                darker += 1;
+            }
+        }
+        if brighter < required && darker < required {
+            return false;
+        }
+
+        (brighter >= required && self.has_arc(&|v| v > center + threshold, circle))
+            || (darker >= required && self.has_arc(&|v| v < center - threshold, circle))
+    }
+
+    fn has_arc(&self, hit: &dyn Fn(i16) -> bool, circle: &[i16; 16]) -> bool {
+        let mut run = 0;
+        // Walk the circle twice so arcs crossing the start are counted.
+        for i in 0..(16 + self.arc_length - 1) {
+            if hit(circle[i%16]) {
+                run += 1;
+                if run >= self.arc_length {
+                    return true;
+                }
+            } else {
+                run = 0;
+            }
+        }
+        false
+    }
+
+    pub fn test(&self, src: &Mat, coordinate: (usize, usize)) -> bool {
+        match FastDetector::circle(src, coordinate) {
+            Some(circle) => {
+                let center = src.get_pixel_by_xy(coordinate.0, coordinate.1)[0] as i16;
+                self.passes(center, &circle, self.threshold as i16)
+            },
+            None => false
+        }
+    }
+
+    // Largest threshold the pixel still passes with, 0 if it is no corner
+    // (or only one at a threshold of 0).
+    pub fn corner_score(&self, src: &Mat, coordinate: (usize, usize)) -> u8 {
+        let circle = match FastDetector::circle(src, coordinate) {
+            Some(circle) => circle,
+            None => return 0
+        };
+        let center = src.get_pixel_by_xy(coordinate.0, coordinate.1)[0] as i16;
+        if !self.passes(center, &circle, self.threshold as i16) {
+            return 0;
+        }
+
+        let mut low = self.threshold as i16;
+        let mut high = 255i16;
+        while low < high {
+            let middle = (low + high + 1)/2;
+            if self.passes(center, &circle, middle) {
+                low = middle;
+            } else {
+                high = middle - 1;
+            }
+        }
+        low as u8
+    }
+
+    pub fn detect_at(&self, src: &Mat, coordinate: (usize, usize)) -> Option<PixelDescription> {
+        if !self.test(src, coordinate) {
+            return None;
+        }
+        let score = self.corner_score(src, coordinate);
+        let circle = FastDetector::circle(src, coordinate)?;
+        let value = src.get_pixel_by_xy(coordinate.0, coordinate.1)[0];
+
+        let mut description = PixelDescription::new();
+        description.coordinate = coordinate;
+        description.description = circle.iter().map(|v| v - value as i16).collect();
+        description.value = value;
+        description.response = score as f32;
+        description.removed = false;
+        Some(description)
+    }
+}
+
+impl Detector for FastDetector {
+    fn detect(&self, src: &Mat, mask: &Rect) -> Vec<PixelDescription> {
+        let mut descriptions = Vec::<PixelDescription>::new();
+        for y in (mask.1)..(mask.1+mask.3) {
+            for x in (mask.0)..(mask.0+mask.2) {
+                if let Some(description) = self.detect_at(src, (x, y)) {
+                    descriptions.push(description);
+                }
+            }
+        }
+        descriptions
+    }
+}
+
+impl CornerScore for FastDetector {
+    fn score(&self, src: &Mat, coordinate: (usize, usize)) -> f32 {
+        self.corner_score(src, coordinate) as f32
+    }
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+
+use super::brief_pattern::PAIRS;
+use super::descriptor::BinaryDescriptor;
+use super::pixel_description::PixelDescription;
+
+// Keypoints and descriptors cached on disk, so matching can be rerun without
+// detecting again. JSON is meant for inspection, the binary format is the
+// compact one.
+
+pub const VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"EVAF";
+
+#[derive(Serialize, Deserialize)]
+struct FeatureFile {
+    version: u32,
+    keypoints: Vec<PixelDescription>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn check_version(version: u32) -> io::Result<()> {
+    if version != VERSION {
+        return Err(invalid_data(format!("Unsupported feature file version {}, expected {}", version, VERSION)));
+    }
+    Ok(())
+}
+
+pub fn to_json(descriptions: &[PixelDescription]) -> String {
+    let file = FeatureFile { version: VERSION, keypoints: descriptions.to_vec() };
+    serde_json::to_string_pretty(&file).expect("Keypoints are always serialisable")
+}
+
+pub fn from_json(json: &str) -> io::Result<Vec<PixelDescription>> {
+    let file: FeatureFile = serde_json::from_str(json)?;
+    check_version(file.version)?;
+    Ok(file.keypoints)
+}
+
+pub fn save_json(descriptions: &[PixelDescription], path: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(Path::new(path))?);
+    writer.write_all(to_json(descriptions).as_bytes())?;
+    writer.flush()
+}
+
+pub fn load_json(path: &str) -> io::Result<Vec<PixelDescription>> {
+    let mut json = String::new();
+    BufReader::new(File::open(Path::new(path))?).read_to_string(&mut json)?;
+    from_json(&json)
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32<W: Write>(writer: &mut W, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_f32<R: Read>(reader: &mut R) -> io::Result<f32> {
+    Ok(f32::from_le_bytes(read_array(reader)?))
+}
+
+// Layout, all little endian: magic, version u32, count u32, then per
+// keypoint x u32, y u32, value u8, removed u8, orientation f32, octave u32,
+// scale f32, response f32, the circle differences as a u32 count of i16 and
+// the descriptor as its u32 test count followed by the used u64 words.
+pub fn write_binary<W: Write>(descriptions: &[PixelDescription], writer: &mut W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    write_u32(writer, VERSION)?;
+    write_u32(writer, descriptions.len() as u32)?;
+
+    for description in descriptions {
+        write_u32(writer, description.coordinate.0 as u32)?;
+        write_u32(writer, description.coordinate.1 as u32)?;
+        writer.write_all(&[description.value, description.removed as u8])?;
+        write_f32(writer, description.orientation)?;
+        write_u32(writer, description.octave as u32)?;
+        write_f32(writer, description.scale)?;
+        write_f32(writer, description.response)?;
+
+        write_u32(writer, description.description.len() as u32)?;
+        for value in &description.description {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        write_u32(writer, description.descriptor.len as u32)?;
+        for word in description.descriptor.words() {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_binary<R: Read>(reader: &mut R) -> io::Result<Vec<PixelDescription>> {
+    let magic: [u8; 4] = read_array(reader)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a feature file".to_string()));
+    }
+    check_version(read_u32(reader)?)?;
+
+    let count = read_u32(reader)? as usize;
+    let mut descriptions = Vec::<PixelDescription>::with_capacity(count.min(1 << 16));
+    for _ in 0..count {
+        let mut description = PixelDescription::new();
+        description.coordinate = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+        let [value, removed] = read_array(reader)?;
+        description.value = value;
+        description.removed = removed != 0;
+        description.orientation = read_f32(reader)?;
+        description.octave = read_u32(reader)? as usize;
+        description.scale = read_f32(reader)?;
+        description.response = read_f32(reader)?;
+
+        let values = read_u32(reader)? as usize;
+        description.description = Vec::with_capacity(values.min(1 << 8));
+        for _ in 0..values {
+            description.description.push(i16::from_le_bytes(read_array(reader)?));
+        }
+
+        let len = read_u32(reader)? as usize;
+        if len > PAIRS {
+            return Err(invalid_data(format!("Descriptor of {} tests, at most {} are supported", len, PAIRS)));
+        }
+        let mut words = Vec::<u64>::new();
+        for _ in 0..(len + 63)/64 {
+            words.push(u64::from_le_bytes(read_array(reader)?));
+        }
+        description.descriptor = BinaryDescriptor::from_words(&words, len).map_err(invalid_data)?;
+
+        descriptions.push(description);
+    }
+    Ok(descriptions)
+}
+
+pub fn save_binary(descriptions: &[PixelDescription], path: &str) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(Path::new(path))?);
+    write_binary(descriptions, &mut writer)?;
+    writer.flush()
+}
+
+pub fn load_binary(path: &str) -> io::Result<Vec<PixelDescription>> {
+    read_binary(&mut BufReader::new(File::open(Path::new(path))?))
+}
@@ -0,0 +1,343 @@
+use std::f64::consts::PI;
+use std::ops::{Add, Mul, Sub};
+
+use super::Mat;
+use super::kernels::Kernel;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn zero() -> Complex {
+        Complex::new(0.0, 0.0)
+    }
+
+    pub fn from_polar(magnitude: f32, phase: f32) -> Complex {
+        Complex::new(magnitude * phase.cos(), magnitude * phase.sin())
+    }
+
+    pub fn conj(&self) -> Complex {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn norm(&self) -> f32 {
+        (self.re * self.re + self.im * self.im).sqrt()
+    }
+
+    pub fn arg(&self) -> f32 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn scale(&self, factor: f32) -> Complex {
+        Complex::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re
+        )
+    }
+}
+
+// In-place, unnormalised DFT of any length. Powers of two use radix-2,
+// everything else goes through Bluestein's chirp-z transform.
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    if n <= 1 {
+        return;
+    }
+    if n.is_power_of_two() {
+        radix2(data, inverse);
+    } else {
+        bluestein(data, inverse);
+    }
+}
+
+pub fn fft_2d(data: &mut [Complex], cols: usize, rows: usize, inverse: bool) {
+    if data.len() != cols * rows {
+        panic!("Spectrum should contain {} values, but there are {}", cols * rows, data.len());
+    }
+
+    for row in data.chunks_mut(cols) {
+        fft(row, inverse);
+    }
+
+    let mut column = vec![Complex::zero(); rows];
+    for x in 0..cols {
+        for y in 0..rows {
+            column[y] = data[y * cols + x];
+        }
+        fft(&mut column, inverse);
+        for y in 0..rows {
+            data[y * cols + x] = column[y];
+        }
+    }
+}
+
+fn radix2(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+
+    let mut j = 0usize;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let twiddles: Vec<Complex> = (0..n / 2).map(|k| {
+        let angle = sign * 2.0 * PI * k as f64 / n as f64;
+        Complex::new(angle.cos() as f32, angle.sin() as f32)
+    }).collect();
+
+    let mut len = 2;
+    while len <= n {
+        let half = len / 2;
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..half {
+                let u = data[start + k];
+                let v = data[start + k + half] * twiddles[k * stride];
+                data[start + k] = u + v;
+                data[start + k + half] = u - v;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+fn bluestein(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    let m = (2 * n - 1).next_power_of_two();
+    let sign = if inverse { 1.0 } else { -1.0 };
+
+    // k² is reduced modulo 2n before the multiplication by π/n to keep the
+    // chirp accurate for long rows.
+    let chirp: Vec<Complex> = (0..n).map(|k| {
+        let k2 = (k as u64 * k as u64) % (2 * n as u64);
+        let angle = sign * PI * k2 as f64 / n as f64;
+        Complex::new(angle.cos() as f32, angle.sin() as f32)
+    }).collect();
+
+    let mut a = vec![Complex::zero(); m];
+    for k in 0..n {
+        a[k] = data[k] * chirp[k];
+    }
+
+    let mut b = vec![Complex::zero(); m];
+    b[0] = chirp[0].conj();
+    for k in 1..n {
+        b[k] = chirp[k].conj();
+        b[m - k] = chirp[k].conj();
+    }
+
+    radix2(&mut a, false);
+    radix2(&mut b, false);
+    for i in 0..m {
+        a[i] = a[i] * b[i];
+    }
+    radix2(&mut a, true);
+
+    let factor = 1.0 / m as f32;
+    for k in 0..n {
+        data[k] = (a[k] * chirp[k]).scale(factor);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Spectrum {
+    pub cols: usize,
+    pub rows: usize,
+    pub data: Vec<Complex>,
+}
+
+impl Spectrum {
+    pub fn from_real(values: &[f32], cols: usize, rows: usize)
+        -> Spectrum
+    {
+        let mut data: Vec<Complex> = values.iter().map(|v| Complex::new(*v, 0.0)).collect();
+        fft_2d(&mut data, cols, rows, false);
+        Spectrum { cols, rows, data }
+    }
+
+    // Real part of the inverse transform, scaled back to the input range.
+    pub fn inverse(&self)
+        -> Vec<f32>
+    {
+        let mut data = self.data.to_vec();
+        fft_2d(&mut data, self.cols, self.rows, true);
+        let factor = 1.0 / (self.cols * self.rows) as f32;
+        data.iter().map(|c| c.re * factor).collect()
+    }
+
+    pub fn get(&self, u: usize, v: usize) -> Complex {
+        self.data[v * self.cols + u]
+    }
+
+    pub fn magnitude(&self) -> Vec<f32> {
+        self.data.iter().map(|c| c.norm()).collect()
+    }
+
+    pub fn phase(&self) -> Vec<f32> {
+        self.data.iter().map(|c| c.arg()).collect()
+    }
+
+    pub fn multiply(&self, other: &Spectrum) -> Spectrum {
+        if self.cols != other.cols || self.rows != other.rows {
+            panic!("Spectrums should have the same size");
+        }
+        let data = self.data.iter().zip(other.data.iter()).map(|(a, b)| *a * *b).collect();
+        Spectrum { cols: self.cols, rows: self.rows, data }
+    }
+
+    // Frequencies are passed unshifted: (0, 0) is the DC term.
+    pub fn filter(&mut self, closure: &dyn Fn(usize, usize, Complex) -> Complex) {
+        for v in 0..self.rows {
+            for u in 0..self.cols {
+                let index = v * self.cols + u;
+                self.data[index] = closure(u, v, self.data[index]);
+            }
+        }
+    }
+
+    // Log-scaled magnitude with the DC term moved to the centre, for viewing.
+    pub fn magnitude_mat(&self)
+        -> Mat
+    {
+        let log_magnitude: Vec<f32> = self.data.iter().map(|c| (1.0 + c.norm()).ln()).collect();
+        let max = log_magnitude.iter().cloned().fold(0f32, f32::max);
+        let max = if max > 0.0 { max } else { 1.0 };
+
+        let mut pixels = vec![0u8; self.cols * self.rows];
+        for v in 0..self.rows {
+            for u in 0..self.cols {
+                let x = (u + self.cols / 2) % self.cols;
+                let y = (v + self.rows / 2) % self.rows;
+                let value = log_magnitude[v * self.cols + u] / max * 255.0;
+                pixels[y * self.cols + x] = value.round() as u8;
+            }
+        }
+        Mat::load_from_vec(pixels, self.cols, self.rows, 1)
+    }
+}
+
+// Valid-region correlation, the same sliding sum `Mat::convolute` computes.
+// Both planes are zero padded to powers of two; the wrap-around of the
+// circular product never reaches the valid region, which is empty when the
+// kernel is larger than the image.
+pub fn fft_correlate(values: &[f32], cols: usize, rows: usize, kernel: &Kernel)
+    -> Vec<f32>
+{
+    let new_cols = (cols + 1).saturating_sub(kernel.width());
+    let new_rows = (rows + 1).saturating_sub(kernel.height());
+    if new_cols == 0 || new_rows == 0 {
+        return Vec::new();
+    }
+    let pad_cols = cols.next_power_of_two();
+    let pad_rows = rows.next_power_of_two();
+
+    let mut image = vec![Complex::zero(); pad_cols * pad_rows];
+    for y in 0..rows {
+        for x in 0..cols {
+            image[y * pad_cols + x] = Complex::new(values[y * cols + x], 0.0);
+        }
+    }
+
+    let mut kernel_plane = vec![Complex::zero(); pad_cols * pad_rows];
+    for (ky, row) in kernel.data.iter().enumerate() {
+        for (kx, value) in row.iter().enumerate() {
+            kernel_plane[ky * pad_cols + kx] = Complex::new(*value, 0.0);
+        }
+    }
+
+    fft_2d(&mut image, pad_cols, pad_rows, false);
+    fft_2d(&mut kernel_plane, pad_cols, pad_rows, false);
+    for i in 0..image.len() {
+        image[i] = image[i] * kernel_plane[i].conj();
+    }
+    fft_2d(&mut image, pad_cols, pad_rows, true);
+
+    let factor = 1.0 / (pad_cols * pad_rows) as f32;
+    let mut result = Vec::<f32>::with_capacity(new_cols * new_rows);
+    for y in 0..new_rows {
+        for x in 0..new_cols {
+            result.push(image[y * pad_cols + x].re * factor);
+        }
+    }
+    result
+}
+
+impl Mat {
+    fn gray_values(&self)
+        -> Vec<f32>
+    {
+        let gray = if self.bytes_per_pixel == 1 { self.clone() } else { self.to_gray() };
+        gray.pixels.iter().map(|p| *p as f32).collect()
+    }
+
+    pub fn dft(&self)
+        -> Spectrum
+    {
+        Spectrum::from_real(&self.gray_values(), self.cols, self.rows)
+    }
+
+    pub fn from_spectrum(spectrum: &Spectrum)
+        -> Mat
+    {
+        let pixels = spectrum.inverse().iter().map(|v| {
+            v.round().clamp(0.0, 255.0) as u8
+        }).collect();
+        Mat::load_from_vec(pixels, spectrum.cols, spectrum.rows, 1)
+    }
+
+    // Same output as `convolute` within rounding, pixels may differ by one,
+    // but the cost no longer grows with the kernel area, which pays off from
+    // roughly 9x9 kernels upwards.
+    pub fn fft_convolute(&self, kernel: Kernel)
+        -> Mat
+    {
+        let new_cols = (self.cols + 1).saturating_sub(kernel.width());
+        let new_rows = (self.rows + 1).saturating_sub(kernel.height());
+
+        let unified_pixels: Vec<f32> = self.gray_values().iter().map(|v| v / 255.0).collect();
+        let response = fft_correlate(&unified_pixels, self.cols, self.rows, &kernel);
+
+        let scale = 255.0 / kernel.max_response();
+        let result_pixels = response.iter().map(|v| (v * scale).abs().round().min(255.0) as u8).collect();
+        Mat::load_from_vec(result_pixels, new_cols, new_rows, 1)
+    }
+}
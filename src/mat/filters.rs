@@ -0,0 +1,40 @@
+use crate::backend::{self, Backend, BackendResult};
+
+use super::Mat;
+use super::kernels::{BorderMode, Kernel};
+
+// Denoising filters for 1 and 3 channel images. Edges repeat the nearest
+// pixel, except for the bilateral filter, which leaves outside samples out.
+impl Mat {
+    // Separable Gaussian covering 3 sigma on each side.
+    pub fn gaussian_blur(&self, sigma: f32) -> Mat {
+        self.convolute_border(&Kernel::gaussian(sigma), BorderMode::Replicate)
+    }
+
+    pub fn gaussian_blur_with(&self, backend: &dyn Backend, sigma: f32) -> BackendResult<Mat> {
+        self.convolute_border_with(backend, &Kernel::gaussian(sigma), BorderMode::Replicate)
+    }
+
+    // Median of the (2*radius + 1)² neighbourhood, for salt and pepper noise.
+    pub fn median_filter(&self, radius: usize) -> Mat {
+        backend::run(|b| self.median_filter_with(b, radius))
+    }
+
+    pub fn median_filter_with(&self, backend: &dyn Backend, radius: usize) -> BackendResult<Mat> {
+        let pixels = backend.median(&self.pixels, self.cols, self.rows, self.bytes_per_pixel, radius)?;
+        Ok(Mat::load_from_vec(pixels, self.cols, self.rows, self.bytes_per_pixel))
+    }
+
+    // Smooths within regions of similar colour and keeps the edges between
+    // them. `sigma_color` is in 0-255 units; `radius` is usually 2*sigma_space.
+    pub fn bilateral_filter(&self, radius: usize, sigma_space: f32, sigma_color: f32) -> Mat {
+        backend::run(|b| self.bilateral_filter_with(b, radius, sigma_space, sigma_color))
+    }
+
+    pub fn bilateral_filter_with(&self, backend: &dyn Backend, radius: usize, sigma_space: f32, sigma_color: f32)
+        -> BackendResult<Mat>
+    {
+        let pixels = backend.bilateral(&self.pixels, self.cols, self.rows, self.bytes_per_pixel, radius, sigma_space, sigma_color)?;
+        Ok(Mat::load_from_vec(pixels, self.cols, self.rows, self.bytes_per_pixel))
+    }
+}
@@ -0,0 +1,99 @@
+use crate::backend;
+
+use super::Mat;
+use super::kernels::{BorderMode, Kernel};
+
+// How float values are turned into pixels: value*scale + offset, rounded
+// and saturated to 0-255. `absolute` takes the magnitude first, which keeps
+// both signs of a gradient or Laplacian response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scaling {
+    pub scale: f32,
+    pub offset: f32,
+    pub absolute: bool,
+}
+
+impl Default for Scaling {
+    fn default() -> Scaling {
+        Scaling { scale: 1.0, offset: 0.0, absolute: false }
+    }
+}
+
+impl Scaling {
+    pub fn new(scale: f32, offset: f32) -> Scaling {
+        Scaling { scale, offset, absolute: false }
+    }
+
+    pub fn absolute(mut self) -> Scaling {
+        self.absolute = true;
+        self
+    }
+
+    pub fn apply(&self, value: f32) -> u8 {
+        let value = if self.absolute { value.abs() } else { value };
+        (value*self.scale + self.offset).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+// Image of raw float values with interleaved channels, e.g. a signed
+// convolution response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatMat {
+    pub cols: usize,
+    pub rows: usize,
+    pub channels: usize,
+    pub values: Vec<f32>,
+}
+
+impl FloatMat {
+    pub fn new(cols: usize, rows: usize, channels: usize, values: Vec<f32>) -> FloatMat {
+        if values.len() != cols*rows*channels {
+            panic!("A {}x{} image with {} channels needs {} values, but {} were given", cols, rows, channels, cols*rows*channels, values.len());
+        }
+        FloatMat { cols, rows, channels, values }
+    }
+
+    pub fn get(&self, x: usize, y: usize, channel: usize) -> f32 {
+        self.values[(y*self.cols + x)*self.channels + channel]
+    }
+
+    // (min, max) over all values, (0, 0) when empty.
+    pub fn min_max(&self) -> (f32, f32) {
+        if self.values.is_empty() {
+            return (0.0, 0.0);
+        }
+        self.values.iter().fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(*v), max.max(*v)))
+    }
+
+    pub fn to_mat(&self, scaling: &Scaling) -> Mat {
+        let pixels = self.values.iter().map(|value| scaling.apply(*value)).collect();
+        Mat::load_from_vec(pixels, self.cols, self.rows, self.channels)
+    }
+
+    // Stretches min..max over 0-255, for looking at a response.
+    pub fn to_mat_stretched(&self) -> Mat {
+        let (min, max) = self.min_max();
+        let range = max - min;
+        let scale = if range > 0.0 { 255.0/range } else { 0.0 };
+        self.to_mat(&Scaling::new(scale, -min*scale))
+    }
+
+    // Same size convolution of every channel, see `Mat::convolute_raw`.
+    pub fn convolute(&self, kernel: &Kernel, border: BorderMode) -> FloatMat {
+        let values = backend::run(|b| b.convolute_border(&self.values, self.cols, self.rows, self.channels, kernel, border));
+        FloatMat::new(self.cols, self.rows, self.channels, values)
+    }
+
+    // Per-element sqrt(a² + b²), e.g. the gradient magnitude of the x and y
+    // Sobel responses.
+    pub fn magnitude(&self, other: &FloatMat) -> FloatMat {
+        let values = self.values.iter().zip(other.values.iter()).map(|(a, b)| a.hypot(*b)).collect();
+        FloatMat::new(self.cols, self.rows, self.channels, values)
+    }
+}
+
+impl Mat {
+    pub fn to_float(&self) -> FloatMat {
+        FloatMat::new(self.cols, self.rows, self.bytes_per_pixel, self.pixels.iter().map(|pixel| *pixel as f32).collect())
+    }
+}
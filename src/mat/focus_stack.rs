@@ -0,0 +1,198 @@
+use super::Mat;
+use super::fast::FastDetector;
+use super::float_mat::FloatMat;
+use super::kernels::{BorderMode, Kernel};
+use super::matcher::{self, MatchOptions};
+use super::nms::{NmsMode, NmsOptions};
+use super::pixel_description::{Direction, PixelDescription};
+use super::transform::{Affine, PHASE_CORRELATION_CUTOFF};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    // The frames are already registered.
+    None,
+    // Phase correlation, for a camera that only drifts.
+    Translation,
+    // Matched keypoints, for focus breathing and small rotations. Falls back
+    // to `Translation` when too few keypoints match.
+    Affine,
+}
+
+#[derive(Debug, Clone)]
+pub struct FocusStackOptions {
+    pub alignment: Alignment,
+    // Index of the frame the others are aligned to.
+    pub reference: usize,
+    // Gaussian sigma spreading the per-pixel Laplacian energy over a region,
+    // larger values pick sources by larger areas.
+    pub focus_sigma: f32,
+    // Gaussian sigma blending the per-frame selections, larger values hide
+    // the seams between sources.
+    pub blend_sigma: f32,
+    // Keypoints detected per frame for `Alignment::Affine`.
+    pub max_features: usize,
+    // Largest plausible drift between frames, as a fraction of the shorter
+    // image side. Frames that seem to move further are left unaligned.
+    pub max_shift: f32,
+}
+
+impl Default for FocusStackOptions {
+    fn default() -> FocusStackOptions {
+        FocusStackOptions { alignment: Alignment::Translation, reference: 0, focus_sigma: 2.0, blend_sigma: 4.0, max_features: 1000, max_shift: 0.1 }
+    }
+}
+
+pub struct FocusStack {
+    pub image: Mat,
+    // Gray image holding the index of the sharpest frame at every pixel.
+    pub depth: Mat,
+    // Maps reference pixel coordinates into each frame.
+    pub transforms: Vec<Affine>,
+}
+
+// Minimum RANSAC inliers, as a count and as a share of the matches, to
+// trust an affine alignment.
+const MIN_INLIERS: usize = 6;
+const MIN_INLIER_RATIO: f32 = 0.3;
+// Phase correlation peaks below this come from frames that do not overlap.
+const MIN_RESPONSE: f32 = 0.15;
+
+fn features(frame: &Mat, max_features: usize) -> Vec<PixelDescription> {
+    let gray = frame.gray();
+    let options = NmsOptions { mode: NmsMode::Adaptive { n: max_features }, ..NmsOptions::default() };
+    gray.detect_features(&FastDetector::default(), &(0, 0, gray.cols, gray.rows), Direction::Oriented, None, &options)
+}
+
+fn translation(reference: &Mat, frame: &Mat, options: &FocusStackOptions) -> Affine {
+    let correlation = reference.phase_correlation(frame, PHASE_CORRELATION_CUTOFF);
+    let (dx, dy) = correlation.shift;
+    let limit = options.max_shift*reference.cols.min(reference.rows) as f32;
+    if correlation.response < MIN_RESPONSE || dx.hypot(dy) > limit {
+        warn!("Rejected a frame shift of ({:.1}, {:.1}) with response {:.2}, leaving the frame unaligned", dx, dy, correlation.response);
+        return Affine::identity();
+    }
+    Affine::translation(dx, dy)
+}
+
+// Mean absolute difference between the reference and the frame sampled
+// through `transform`, over the reference pixels that land inside the frame.
+// Both are smoothed gray images, so their focus differences matter less
+// than the misalignment.
+fn residual(reference: &Mat, frame: &Mat, transform: &Affine) -> f32 {
+    let warped = frame.warp_affine(transform, reference.cols, reference.rows);
+    let (max_x, max_y) = (frame.cols as f32 - 1.0, frame.rows as f32 - 1.0);
+    let (mut total, mut count) = (0f64, 0usize);
+    for y in 0..reference.rows {
+        for x in 0..reference.cols {
+            let (src_x, src_y) = transform.apply(x as f32, y as f32);
+            if src_x >= 0.0 && src_y >= 0.0 && src_x <= max_x && src_y <= max_y {
+                let i = y*reference.cols + x;
+                total += (reference.pixels[i] as f64 - warped.pixels[i] as f64).abs();
+                count += 1;
+            }
+        }
+    }
+    if count == 0 { f32::MAX } else { (total/count as f64) as f32 }
+}
+
+fn affine(reference: &Mat, reference_features: &[PixelDescription], frame: &Mat, options: &FocusStackOptions) -> Affine {
+    let fallback = translation(reference, frame, options);
+    let frame_features = features(frame, options.max_features);
+    let matches = matcher::filtered_match(&frame_features, reference_features, &MatchOptions::default());
+    let pairs: Vec<_> = matches.iter().map(|m| {
+        let from = reference_features[m.train].coordinate;
+        let to = frame_features[m.query].coordinate;
+        ((from.0 as f32, from.1 as f32), (to.0 as f32, to.1 as f32))
+    }).collect();
+
+    let transform = match Affine::ransac(&pairs, 3.0, 500) {
+        Some((transform, inliers)) if inliers.len() >= MIN_INLIERS && inliers.len() as f32 >= MIN_INLIER_RATIO*pairs.len() as f32 => transform,
+        _ => {
+            warn!("Too few of {} keypoint matches agree on an affine alignment, aligning by translation", pairs.len());
+            return fallback;
+        }
+    };
+
+    let smooth = |mat: &Mat| mat.gray().gaussian_blur(2.0);
+    let (reference, frame) = (smooth(reference), smooth(frame));
+    if residual(&reference, &frame, &transform) > residual(&reference, &frame, &fallback) {
+        warn!("The affine alignment fits worse than the translation, aligning by translation");
+        return fallback;
+    }
+    transform
+}
+
+// Laplacian energy of the gray frame, spread by a Gaussian.
+fn focus_measure(frame: &Mat, sigma: f32) -> FloatMat {
+    let laplacian = frame.gray().convolute_raw(&Kernel::laplation_4(), BorderMode::Reflect);
+    let energy = FloatMat::new(laplacian.cols, laplacian.rows, 1, laplacian.values.iter().map(|v| v*v).collect());
+    energy.convolute(&Kernel::gaussian(sigma), BorderMode::Reflect)
+}
+
+// Merges frames of one scene taken at different focus depths into an image
+// sharp everywhere, with the default options.
+pub fn focus_stack(frames: &[Mat]) -> Mat {
+    focus_stack_with(frames, &FocusStackOptions::default()).image
+}
+
+pub fn focus_stack_with(frames: &[Mat], options: &FocusStackOptions) -> FocusStack {
+    if frames.is_empty() || frames.len() > 256 {
+        panic!("Focus stacking needs 1 to 256 frames, but there are {}", frames.len());
+    }
+    let reference = &frames[options.reference.min(frames.len() - 1)];
+    let (cols, rows, channels) = (reference.cols, reference.rows, reference.bytes_per_pixel);
+    if frames.iter().any(|frame| frame.cols != cols || frame.rows != rows || frame.bytes_per_pixel != channels) {
+        panic!("Focus stacking needs frames of the same size and channels");
+    }
+
+    let reference_features = match options.alignment {
+        Alignment::Affine => features(reference, options.max_features),
+        _ => Vec::new()
+    };
+    let transforms: Vec<Affine> = frames.iter().map(|frame| {
+        if std::ptr::eq(frame, reference) {
+            return Affine::identity();
+        }
+        match options.alignment {
+            Alignment::None => Affine::identity(),
+            Alignment::Translation => translation(reference, frame, options),
+            Alignment::Affine => affine(reference, &reference_features, frame, options)
+        }
+    }).collect();
+    debug!("Focus stack transforms: {:?}", transforms);
+
+    let aligned: Vec<Mat> = frames.iter().zip(transforms.iter()).map(|(frame, transform)| {
+        if *transform == Affine::identity() { frame.clone() } else { frame.warp_affine(transform, cols, rows) }
+    }).collect();
+    let measures: Vec<FloatMat> = aligned.iter().map(|frame| focus_measure(frame, options.focus_sigma)).collect();
+
+    // Sharpest frame per pixel, the first one on ties.
+    let depth: Vec<u8> = (0..cols*rows).map(|i| {
+        (0..measures.len()).fold(0, |best, f| if measures[f].values[i] > measures[best].values[i] { f } else { best }) as u8
+    }).collect();
+
+    // Blurred one-hot selections become the blending weights.
+    let blur = Kernel::gaussian(options.blend_sigma);
+    let weights: Vec<FloatMat> = (0..frames.len()).map(|f| {
+        let selection = depth.iter().map(|d| if *d as usize == f { 1.0 } else { 0.0 }).collect();
+        FloatMat::new(cols, rows, 1, selection).convolute(&blur, BorderMode::Reflect)
+    }).collect();
+
+    let mut pixels = Vec::<u8>::with_capacity(cols*rows*channels);
+    for i in 0..cols*rows {
+        let total: f32 = weights.iter().map(|w| w.values[i]).sum();
+        for chn in 0..channels {
+            let value: f32 = aligned.iter().zip(weights.iter())
+                .map(|(frame, w)| frame.pixels[i*channels + chn] as f32*w.values[i])
+                .sum();
+            let value = if total > 0.0 { value/total } else { aligned[depth[i] as usize].pixels[i*channels + chn] as f32 };
+            pixels.push(value.round().clamp(0.0, 255.0) as u8);
+        }
+    }
+
+    FocusStack {
+        image: Mat::load_from_vec(pixels, cols, rows, channels),
+        depth: Mat::load_from_vec(depth, cols, rows, 1),
+        transforms,
+    }
+}
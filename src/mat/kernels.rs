@@ -1,14 +1,100 @@
-#[derive(Debug)]
+// How samples outside the image are read by border-aware convolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BorderMode {
+    // Every outside sample has this value.
+    Constant(f32),
+    // The nearest edge pixel: aaa|abcd|ddd.
+    Replicate,
+    // Mirrored, the edge pixel included: cba|abcd|dcb.
+    Reflect,
+    // The opposite side: bcd|abcd|abc.
+    Wrap,
+}
+
+impl BorderMode {
+    // Index of the sample read for position `i` of a line of `n` samples,
+    // None when the constant is read instead.
+    pub fn index(&self, i: i64, n: usize) -> Option<usize> {
+        let n = n as i64;
+        if i >= 0 && i < n {
+            return Some(i as usize);
+        }
+        match self {
+            BorderMode::Constant(_) => None,
+            BorderMode::Replicate => Some(i.clamp(0, n - 1) as usize),
+            BorderMode::Reflect => {
+                let m = i.rem_euclid(2*n);
+                Some(if m < n { m } else { 2*n - 1 - m } as usize)
+            },
+            BorderMode::Wrap => Some(i.rem_euclid(n) as usize)
+        }
+    }
+
+    pub fn constant(&self) -> f32 {
+        match self {
+            BorderMode::Constant(value) => *value,
+            _ => 0.0
+        }
+    }
+
+    // Code used by the OpenCL kernels.
+    #[cfg(feature = "opencl")]
+    pub(crate) fn code(&self) -> i32 {
+        match self {
+            BorderMode::Constant(_) => 0,
+            BorderMode::Replicate => 1,
+            BorderMode::Reflect => 2,
+            BorderMode::Wrap => 3
+        }
+    }
+}
+
+// One correlation over the image: `values` row by row, `width` x `height`,
+// with the kernel element at `anchor` over the output pixel.
+#[derive(Debug, Clone)]
+pub struct Pass {
+    pub values: Vec<f32>,
+    pub width: usize,
+    pub height: usize,
+    pub anchor: (usize, usize),
+    pub border: BorderMode,
+}
+
+// Correlation weights, `data` row by row. Kernels may be rectangular; the
+// anchor is the (x, y) element placed over the output pixel by same size
+// convolution and defaults to the centre.
+#[derive(Debug, Clone)]
 pub struct Kernel {
     pub data: Vec<Vec<f32>>,
+    pub anchor: (usize, usize),
 }
 
 impl Kernel {
     pub fn load(data: Vec<Vec<f32>>)
         -> Kernel
     {
-        let kernel = Kernel { data: data };
-        kernel
+        let width = data.first().map(|row| row.len()).unwrap_or(0);
+        if data.iter().any(|row| row.len() != width) {
+            panic!("Kernel rows should all have {} values", width);
+        }
+        let anchor = (width/2, data.len()/2);
+        Kernel { data, anchor }
+    }
+
+    // `values` row by row.
+    pub fn from_values(width: usize, height: usize, values: &[f32]) -> Kernel {
+        if values.len() != width*height {
+            panic!("A {}x{} kernel needs {} values, but {} were given", width, height, width*height, values.len());
+        }
+        Kernel::load(values.chunks(width.max(1)).map(|row| row.to_vec()).collect())
+    }
+
+    pub fn with_anchor(mut self, x: usize, y: usize) -> Kernel {
+        if x >= self.width() || y >= self.height() {
+            panic!("Anchor ({}, {}) is outside the {}x{} kernel", x, y, self.width(), self.height());
+        }
+        self.anchor = (x, y);
+        self
     }
 
     pub fn laplation_8() -> Kernel {
@@ -36,40 +122,222 @@ impl Kernel {
         ])
     }
 
+    // Normalised Gaussian covering 3 sigma on each side.
+    pub fn gaussian(sigma: f32) -> Kernel {
+        let radius = (3.0*sigma).ceil().max(0.0) as usize;
+        Kernel::gaussian_sized(2*radius + 1, sigma)
+    }
+
+    pub fn gaussian_sized(size: usize, sigma: f32) -> Kernel {
+        let center = (size as f32 - 1.0)/2.0;
+        let line: Vec<f32> = (0..size).map(|i| {
+            let d = i as f32 - center;
+            if sigma > 0.0 { (-d*d/(2.0*sigma*sigma)).exp() } else if d.abs() < 1.0 { 1.0 } else { 0.0 }
+        }).collect();
+        Kernel::load(line.iter().map(|y| line.iter().map(|x| x*y).collect()).collect()).normalized()
+    }
+
+    // Mean of a `width` x `height` neighbourhood.
+    pub fn box_blur(width: usize, height: usize) -> Kernel {
+        Kernel::from_values(width, height, &vec![1.0; width*height]).normalized()
+    }
+
+    // Horizontal gradient, positive where the image gets brighter to the right.
+    pub fn sobel_x() -> Kernel {
+        Kernel::load(vec![
+            vec![-1.0, 0.0, 1.0],
+            vec![-2.0, 0.0, 2.0],
+            vec![-1.0, 0.0, 1.0]
+        ])
+    }
+
+    // Vertical gradient, positive where the image gets brighter downwards.
+    pub fn sobel_y() -> Kernel {
+        Kernel::sobel_x().transposed()
+    }
+
+    pub fn scharr_x() -> Kernel {
+        Kernel::load(vec![
+            vec![-3.0, 0.0, 3.0],
+            vec![-10.0, 0.0, 10.0],
+            vec![-3.0, 0.0, 3.0]
+        ])
+    }
+
+    pub fn scharr_y() -> Kernel {
+        Kernel::scharr_x().transposed()
+    }
+
+    pub fn sharpen() -> Kernel {
+        Kernel::load(vec![
+            vec![0.0, -1.0, 0.0],
+            vec![-1.0, 5.0, -1.0],
+            vec![0.0, -1.0, 0.0]
+        ])
+    }
+
+    // Identity plus `amount` times the detail a Gaussian of `sigma` removes.
+    pub fn unsharp(sigma: f32, amount: f32) -> Kernel {
+        let mut kernel = Kernel::gaussian(sigma).scaled(-amount);
+        let (x, y) = kernel.anchor;
+        kernel.data[y][x] += 1.0 + amount;
+        kernel
+    }
+
+    pub fn emboss() -> Kernel {
+        Kernel::load(vec![
+            vec![-2.0, -1.0, 0.0],
+            vec![-1.0, 1.0, 1.0],
+            vec![0.0, 1.0, 2.0]
+        ])
+    }
+
+    // Mean along a line of `length` pixels through the centre, `angle` in
+    // degrees counter-clockwise from the x axis.
+    pub fn motion_blur(length: usize, angle: f32) -> Kernel {
+        let size = length.max(1);
+        let center = (size as f32 - 1.0)/2.0;
+        let (sin, cos) = angle.to_radians().sin_cos();
+        // Steps along the longer axis are whole pixels, so the line spans
+        // the square at any angle.
+        let step = 1.0/cos.abs().max(sin.abs());
+        let mut data = vec![vec![0f32; size]; size];
+        for i in 0..size {
+            let t = (i as f32 - center)*step;
+            let x = (center + t*cos).round() as usize;
+            let y = (center - t*sin).round() as usize;
+            data[y.min(size - 1)][x.min(size - 1)] = 1.0;
+        }
+        Kernel::load(data).normalized()
+    }
+
+    pub fn width(&self) -> usize {
+        self.data.first().map(|row| row.len()).unwrap_or(0)
+    }
+
+    pub fn height(&self) -> usize {
+        self.data.len()
+    }
+
+    // The side of a square kernel.
     pub fn size(&self) -> usize {
         self.data.len()
     }
 
+    pub fn sum(&self) -> f32 {
+        self.data.iter().flatten().sum()
+    }
+
+    // Largest response magnitude on values in 0..1: all positive weights
+    // on 1 and all negative ones on 0, or the other way round.
+    pub fn max_response(&self) -> f32 {
+        let positive: f32 = self.data.iter().flatten().filter(|v| **v > 0.0).sum();
+        let negative: f32 = self.data.iter().flatten().filter(|v| **v < 0.0).sum();
+        positive.max(-negative)
+    }
+
+    pub fn scaled(mut self, factor: f32) -> Kernel {
+        for value in self.data.iter_mut().flatten() {
+            *value *= factor;
+        }
+        self
+    }
+
+    // Weights summing to 1, so flat areas keep their brightness. Kernels
+    // summing to 0 (derivatives) are returned unchanged.
+    pub fn normalized(self) -> Kernel {
+        let sum = self.sum();
+        if sum.abs() < f32::EPSILON {
+            return self;
+        }
+        self.scaled(1.0/sum)
+    }
+
+    // Absolute weights summing to 1, for kernels that sum to 0.
+    pub fn normalized_l1(self) -> Kernel {
+        let sum: f32 = self.data.iter().flatten().map(|value| value.abs()).sum();
+        if sum < f32::EPSILON {
+            return self;
+        }
+        self.scaled(1.0/sum)
+    }
+
+    pub fn transposed(&self) -> Kernel {
+        let data = (0..self.width()).map(|x| self.data.iter().map(|row| row[x]).collect()).collect();
+        Kernel { data, anchor: (self.anchor.1, self.anchor.0) }
+    }
+
     pub fn flatten(&self)
         -> Vec<f32> 
     {
-        let mut vec = Vec::with_capacity(self.elements());
-        for row in self.data.to_vec() {
-            for value in row {
-                vec.push(value);
-            }
-        }
-        vec
+        self.data.iter().flatten().cloned().collect()
     }
 
     pub fn elements(&self)
         -> usize
     {
-        self.data.len()*self.data.len()
+        self.width()*self.height()
+    }
+
+    // (column, row) vectors whose outer product is the kernel, None when it
+    // is not rank-1.
+    pub fn separate(&self) -> Option<(Vec<f32>, Vec<f32>)> {
+        let values = self.flatten();
+        let width = self.width();
+        let (pivot, max) = values.iter().enumerate()
+            .fold((0, 0f32), |best, (i, v)| if v.abs() > best.1 { (i, v.abs()) } else { best });
+        if max == 0.0 {
+            return None;
+        }
+
+        let (pivot_row, pivot_col) = (pivot/width, pivot%width);
+        let column: Vec<f32> = (0..self.height()).map(|row| values[row*width + pivot_col]).collect();
+        let row: Vec<f32> = (0..width).map(|col| values[pivot_row*width + col]/values[pivot]).collect();
+        let tolerance = max*1e-5;
+        for y in 0..self.height() {
+            for x in 0..width {
+                if (column[y]*row[x] - values[y*width + x]).abs() > tolerance {
+                    return None;
+                }
+            }
+        }
+        Some((column, row))
+    }
+
+    // The correlations that apply the kernel with `border`: a horizontal
+    // and a vertical one for rank-1 kernels, the full kernel otherwise. The
+    // second pass reads rows outside the image, so its constant is the
+    // first pass applied to a constant row.
+    pub fn passes(&self, border: BorderMode) -> Vec<Pass> {
+        let (width, height) = (self.width(), self.height());
+        let (anchor_x, anchor_y) = self.anchor;
+        match self.separate() {
+            Some((column, row)) if width > 1 && height > 1 => {
+                let vertical_border = match border {
+                    BorderMode::Constant(value) => BorderMode::Constant(value*row.iter().sum::<f32>()),
+                    other => other
+                };
+                vec![
+                    Pass { values: row, width, height: 1, anchor: (anchor_x, 0), border },
+                    Pass { values: column, width: 1, height, anchor: (0, anchor_y), border: vertical_border }
+                ]
+            },
+            _ => vec![Pass { values: self.flatten(), width, height, anchor: self.anchor, border }]
+        }
     }
 
     pub fn indexes(&self, index: usize, chrunk_size: usize, total_elements: usize)
         -> (bool, Vec<usize>)
     {
-        let mut indexes = Vec::with_capacity(self.size()*self.size());
+        let mut indexes = Vec::with_capacity(self.elements());
         let offset = index%chrunk_size;
 
-        if (offset + self.size()) > chrunk_size {
+        if (offset + self.width()) > chrunk_size {
             return (false, vec![]);
         }
 
-        for row in 0..self.size() {
-            for col in 0..self.size() {
+        for row in 0..self.height() {
+            for col in 0..self.width() {
                 let id = row * chrunk_size + col + index;
                 if id >= total_elements {
                     return (false, vec![]);
@@ -80,4 +348,4 @@ impl Kernel {
         return (true, indexes);
     }
 
-}
\ No newline at end of file
+}
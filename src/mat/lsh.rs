@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index;
+
+use super::brief_pattern::PAIRS;
+use super::descriptor::BinaryDescriptor;
+use super::matcher::{self, Match, MatchOptions};
+use super::pixel_description::PixelDescription;
+
+#[derive(Debug, Clone)]
+pub struct LshParams {
+    // Independent hash tables; more tables find more true neighbours.
+    pub tables: usize,
+    // Descriptor bits sampled per table key. Longer keys mean smaller buckets.
+    pub key_bits: usize,
+    // Multi-probe depth: buckets whose key differs from the query key in up
+    // to this many bits (0, 1 or 2) are searched as well. This is the main
+    // knob trading recall for speed.
+    pub probe_level: usize,
+    pub seed: u64,
+}
+
+impl Default for LshParams {
+    fn default() -> LshParams {
+        LshParams { tables: 6, key_bits: 20, probe_level: 1, seed: 0x5eed }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LshTable {
+    bits: Vec<usize>,
+    buckets: HashMap<u32, Vec<usize>>,
+}
+
+impl LshTable {
+    fn key(&self, descriptor: &BinaryDescriptor) -> u32 {
+        let mut key = 0u32;
+        for (i, bit) in self.bits.iter().enumerate() {
+            if descriptor.get(*bit) {
+                key |= 1 << i;
+            }
+        }
+        key
+    }
+}
+
+// Multi-probe LSH over Hamming space, built once from a descriptor set
+// and queried many times.
+#[derive(Debug, Clone)]
+pub struct LshIndex {
+    descriptors: Vec<BinaryDescriptor>,
+    tables: Vec<LshTable>,
+    params: LshParams,
+}
+
+impl LshIndex {
+    pub fn new(descriptors: &[BinaryDescriptor], params: LshParams)
+        -> LshIndex
+    {
+        if params.key_bits == 0 || params.key_bits > 32 {
+            panic!("LSH keys should use 1 to 32 bits, but {} were requested", params.key_bits);
+        }
+
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        let mut tables = Vec::<LshTable>::with_capacity(params.tables);
+        for _ in 0..params.tables {
+            let mut table = LshTable {
+                bits: index::sample(&mut rng, PAIRS, params.key_bits).into_vec(),
+                buckets: HashMap::new(),
+            };
+            for (i, descriptor) in descriptors.iter().enumerate() {
+                let key = table.key(descriptor);
+                table.buckets.entry(key).or_default().push(i);
+            }
+            tables.push(table);
+        }
+
+        LshIndex { descriptors: descriptors.to_vec(), tables, params }
+    }
+
+    pub fn from_descriptions(descriptions: &[PixelDescription], params: LshParams)
+        -> LshIndex
+    {
+        let descriptors: Vec<BinaryDescriptor> = descriptions.iter().map(|d| d.descriptor).collect();
+        LshIndex::new(&descriptors, params)
+    }
+
+    pub fn len(&self) -> usize {
+        self.descriptors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.descriptors.is_empty()
+    }
+
+    fn probe_keys(&self, key: u32) -> Vec<u32> {
+        let bits = self.params.key_bits;
+        let mut keys = vec![key];
+        if self.params.probe_level >= 1 {
+            for i in 0..bits {
+                keys.push(key ^ (1 << i));
+            }
+        }
+        if self.params.probe_level >= 2 {
+            for i in 0..bits {
+                for j in (i + 1)..bits {
+                    keys.push(key ^ (1 << i) ^ (1 << j));
+                }
+            }
+        }
+        keys
+    }
+
+    // Approximate `k` nearest neighbours as (index, distance), closest first;
+    // empty for `k == 0`.
+    pub fn knn(&self, query: &BinaryDescriptor, k: usize)
+        -> Vec<(usize, u32)>
+    {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut visited = vec![false; self.descriptors.len()];
+        let mut nearest = Vec::<(usize, u32)>::with_capacity(k + 1);
+
+        for table in &self.tables {
+            for key in self.probe_keys(table.key(query)) {
+                let bucket = match table.buckets.get(&key) {
+                    Some(bucket) => bucket,
+                    None => continue
+                };
+                for &candidate in bucket {
+                    if visited[candidate] {
+                        continue;
+                    }
+                    visited[candidate] = true;
+
+                    let other = &self.descriptors[candidate];
+                    if other.len != query.len {
+                        continue;
+                    }
+                    let distance = query.hamming(other);
+                    if nearest.len() == k && distance >= nearest[k - 1].1 {
+                        continue;
+                    }
+                    let position = nearest.iter().position(|n| n.1 > distance).unwrap_or(nearest.len());
+                    nearest.insert(position, (candidate, distance));
+                    nearest.truncate(k);
+                }
+            }
+        }
+        nearest
+    }
+
+    // Same layout as `matcher::knn_match`, with this index as the train set.
+    pub fn knn_match(&self, queries: &[PixelDescription], k: usize)
+        -> Vec<Vec<Match>>
+    {
+        queries.iter().enumerate().map(|(query, description)| {
+            self.knn(&description.descriptor, k).iter().map(|&(train, distance)| {
+                Match { query, train, distance }
+            }).collect()
+        }).collect()
+    }
+
+    // `matcher::filtered_match` against the indexed set. The reverse pass of
+    // the cross-check goes through a temporary index over the queries.
+    pub fn filtered_match(&self, queries: &[PixelDescription], options: &MatchOptions)
+        -> Vec<Match>
+    {
+        let k = match options.ratio {
+            Some(_) => options.k.max(2),
+            None => options.k.max(1)
+        };
+        let forward = self.knn_match(queries, k);
+
+        let backward = match options.cross_check {
+            true => {
+                let reverse = LshIndex::from_descriptions(queries, self.params.clone());
+                self.descriptors.iter().enumerate().map(|(query, descriptor)| {
+                    reverse.knn(descriptor, 1).iter().map(|&(train, distance)| {
+                        Match { query, train, distance }
+                    }).collect()
+                }).collect()
+            },
+            false => Vec::new()
+        };
+
+        matcher::filter_knn(&forward, &backward, options)
+    }
+}
@@ -0,0 +1,112 @@
+use super::pixel_description::PixelDescription;
+
+// `query` and `train` index into the two description lists handed to the
+// matcher, `distance` is the Hamming distance between their descriptors.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    pub query: usize,
+    pub train: usize,
+    pub distance: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchOptions {
+    // Neighbours retrieved per keypoint, the ratio test needs at least 2.
+    pub k: usize,
+    // Lowe's ratio test: keep a match only if best < ratio * second best.
+    pub ratio: Option<f32>,
+    // Keep a match only if it is also the best match in the other direction.
+    pub cross_check: bool,
+    pub max_distance: Option<u32>,
+}
+
+impl Default for MatchOptions {
+    fn default() -> MatchOptions {
+        MatchOptions { k: 2, ratio: Some(0.8), cross_check: true, max_distance: None }
+    }
+}
+
+// The `k` nearest train descriptors of every query, closest first; empty
+// lists for `k == 0`. Descriptors of different length are not comparable and
+// never match.
+pub fn knn_match(queries: &[PixelDescription], train: &[PixelDescription], k: usize)
+    -> Vec<Vec<Match>>
+{
+    let mut result = Vec::<Vec<Match>>::with_capacity(queries.len());
+    if k == 0 {
+        result.resize(queries.len(), Vec::new());
+        return result;
+    }
+    for (query_index, query) in queries.iter().enumerate() {
+        let mut nearest = Vec::<Match>::with_capacity(k + 1);
+        for (train_index, other) in train.iter().enumerate() {
+            if query.descriptor.len != other.descriptor.len {
+                continue;
+            }
+            let distance = query.descriptor.hamming(&other.descriptor);
+            if nearest.len() == k && distance >= nearest[k - 1].distance {
+                continue;
+            }
+            let position = nearest.iter().position(|m| m.distance > distance).unwrap_or(nearest.len());
+            nearest.insert(position, Match { query: query_index, train: train_index, distance });
+            nearest.truncate(k);
+        }
+        result.push(nearest);
+    }
+    result
+}
+
+pub fn filtered_match(queries: &[PixelDescription], train: &[PixelDescription], options: &MatchOptions)
+    -> Vec<Match>
+{
+    let k = match options.ratio {
+        Some(_) => options.k.max(2),
+        None => options.k.max(1)
+    };
+    let forward = knn_match(queries, train, k);
+    let backward = match options.cross_check {
+        true => knn_match(train, queries, 1),
+        false => Vec::new()
+    };
+    filter_knn(&forward, &backward, options)
+}
+
+// Applies ratio test, distance limit and cross-check to knn results that
+// were already retrieved, so any nearest-neighbour search can feed it.
+// `backward` holds the best query for each train descriptor and is only
+// read when `cross_check` is set.
+pub fn filter_knn(forward: &[Vec<Match>], backward: &[Vec<Match>], options: &MatchOptions)
+    -> Vec<Match>
+{
+    let mut matches = Vec::<Match>::new();
+    for neighbours in forward {
+        let best = match neighbours.first() {
+            Some(best) => *best,
+            None => continue
+        };
+
+        if let Some(max_distance) = options.max_distance {
+            if best.distance > max_distance {
+                continue;
+            }
+        }
+
+        if let Some(ratio) = options.ratio {
+            if let Some(second) = neighbours.get(1) {
+                if best.distance as f32 >= ratio * second.distance as f32 {
+                    continue;
+                }
+            }
+        }
+
+        if options.cross_check {
+            match backward.get(best.train).and_then(|b| b.first()) {
+                Some(reverse) if reverse.train == best.query => {},
+                _ => continue
+            }
+        }
+
+        matches.push(best);
+    }
+    matches
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
@@ -8,21 +9,35 @@ extern crate png;
 use jpeg_decoder::Decoder;
 use jpeg_decoder::PixelFormat;
 
-use std::time::{Instant};
-use super::CL;
-
+use crate::backend::{self, Backend, BackendResult};
+use crate::progress::{LogObserver, Observer, timed};
+
+pub mod blur_detection;
+pub mod brief_pattern;
+pub mod corners;
+pub mod descriptor;
+pub mod fast;
+pub mod feature_file;
+pub mod fft;
+pub mod filters;
+pub mod float_mat;
+pub mod focus_stack;
 pub mod kernels;
+pub mod lsh;
+pub mod matcher;
+pub mod nms;
 pub mod pixel_description;
+pub mod pyramid;
+pub mod sharpness;
 pub mod transform;
 
 use pixel_description::PixelDescription;
 use pixel_description::Direction;
+use descriptor::BinaryDescriptor;
+use corners::Rect;
+use float_mat::{FloatMat, Scaling};
 
 
-lazy_static! {
-    static ref CL_INSTANCE: CL = CL::new();
-}
-
 #[derive(Debug, Clone)]
 pub struct Mat {
     pub cols: usize,
@@ -102,7 +117,7 @@ impl Mat {
         let decoder = png::Decoder::new(File::open(path).unwrap());
         let (output_info, mut reader) = decoder.read_info().unwrap();
 
-        println!("{:?}", output_info.color_type);
+        trace!("{:?}", output_info.color_type);
 
         let bytes = reader.info().bytes_per_pixel();
         let mut buf = vec![0; output_info.buffer_size()];
@@ -164,30 +179,49 @@ impl Mat {
     }
 
     pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Mat {
-        let new_data = CL_INSTANCE.cl_crop(&self.pixels, self.cols as i32, x as i32, y as i32, width as i32, height as i32, self.bytes_per_pixel as i32).unwrap();
-        Mat::load_from_vec(new_data, width, height, self.bytes_per_pixel)
+        backend::run(|b| self.crop_with(b, x, y, width, height))
     }
 
+    pub fn crop_with(&self, backend: &dyn Backend, x: usize, y: usize, width: usize, height: usize) -> BackendResult<Mat> {
+        let new_data = backend.crop(&self.pixels, self.cols, self.bytes_per_pixel, &(x, y, width, height))?;
+        Ok(Mat::load_from_vec(new_data, width, height, self.bytes_per_pixel))
+    }
 
-    // pub fn resize(&self, width: usize, height: usize)
-    //     -> Mat
-    // {
-    //     let scale_x = (self.cols as f32)/(width as f32);
-    //     let scale_y = (self.rows as f32)/(height as f32);
-    //     let mut new_data = vec![vec![vec![0u8; 4]; width]; height];
-    //     let src_data = &self.data;
-    //     for y in 0..height {
-    //         for x in 0..width {
-    //             let src_x = ((x as f32)*scale_x).round() as usize;
-    //             let src_y = ((y as f32)*scale_y).round() as usize;
-    //             if src_x < self.cols as usize && src_y < self.rows as usize {
-    //                 new_data[y][x] = src_data[src_y][src_x].to_vec();
-    //             }
-    //         }
-    //     }
-    //     let mat = Mat {cols: width as usize, rows: height as usize, bytes_per_pixel: self.bytes_per_pixel, data: new_data, size: height*width};
-    //     mat
-    // }
+
+    // Bilinear resampling, pixel centres are aligned between both sizes. An
+    // empty image resizes to a black one.
+    pub fn resize(&self, width: usize, height: usize)
+        -> Mat
+    {
+        let channels = self.bytes_per_pixel;
+        let scale_x = (self.cols as f32)/(width as f32);
+        let scale_y = (self.rows as f32)/(height as f32);
+        let mut new_data = Vec::<u8>::with_capacity(width*height*channels);
+        if self.cols == 0 || self.rows == 0 {
+            new_data.resize(width*height*channels, 0);
+            return Mat::load_from_vec(new_data, width, height, channels);
+        }
+
+        for y in 0..height {
+            let src_y = ((y as f32 + 0.5)*scale_y - 0.5).clamp(0.0, (self.rows - 1) as f32);
+            let y0 = src_y.floor() as usize;
+            let y1 = (y0 + 1).min(self.rows - 1);
+            let fy = src_y - y0 as f32;
+            for x in 0..width {
+                let src_x = ((x as f32 + 0.5)*scale_x - 0.5).clamp(0.0, (self.cols - 1) as f32);
+                let x0 = src_x.floor() as usize;
+                let x1 = (x0 + 1).min(self.cols - 1);
+                let fx = src_x - x0 as f32;
+                for chn in 0..channels {
+                    let value = |xx: usize, yy: usize| self.pixels[(yy*self.cols + xx)*channels + chn] as f32;
+                    let top = value(x0, y0)*(1.0 - fx) + value(x1, y0)*fx;
+                    let bottom = value(x0, y1)*(1.0 - fx) + value(x1, y1)*fx;
+                    new_data.push((top*(1.0 - fy) + bottom*fy).round() as u8);
+                }
+            }
+        }
+        Mat::load_from_vec(new_data, width, height, channels)
+    }
 
     pub fn merge(&mut self, other: &Mat, x: usize, y: usize) {
         for row in 0..other.rows {
@@ -268,75 +302,96 @@ impl Mat {
     pub fn to_gray(&self)
         -> Mat
     {
-        let new_data = CL_INSTANCE.cl_to_gray(&self.pixels, self.bytes_per_pixel).unwrap();
-        Mat::load_from_vec(new_data, self.cols, self.rows, 1)
+        backend::run(|b| self.to_gray_with(b))
+    }
+
+    pub fn to_gray_with(&self, backend: &dyn Backend) -> BackendResult<Mat> {
+        let new_data = backend.to_gray(&self.pixels, self.bytes_per_pixel)?;
+        Ok(Mat::load_from_vec(new_data, self.cols, self.rows, 1))
     }
 
+    // This image if it is gray already, its gray version otherwise.
+    pub(crate) fn gray(&self) -> Mat {
+        if self.bytes_per_pixel == 1 { self.clone() } else { self.to_gray() }
+    }
+
+    // Valid region response magnitude of the gray image, scaled so the
+    // strongest response the kernel can give maps to 255 (see
+    // `Kernel::max_response`). `convolute_raw` keeps the signed values.
+    // Kernels larger than the image leave an empty result.
     pub fn convolute(&self, kernel: kernels::Kernel)
-        // -> Vec<u8>
         -> Mat
     {
-        let new_cols = self.cols as usize - kernel.size() + 1;
-        let new_rows = self.rows as usize - kernel.size() + 1;
-        let result_size = new_cols * new_rows;
+        let new_cols = (self.cols + 1).saturating_sub(kernel.width());
+        let new_rows = (self.rows + 1).saturating_sub(kernel.height());
+        if new_cols == 0 || new_rows == 0 {
+            return Mat::load_from_vec(Vec::new(), new_cols, new_rows, 1);
+        }
+        let gray = self.gray();
+        let unified_pixels: Vec<f32> = gray.pixels.iter().map(|pixel| *pixel as f32/255.0).collect();
+        let response = backend::run(|b| b.convolute(&unified_pixels, self.cols, self.rows, &kernel));
 
-        let mut result_pixels = Vec::<u8>::with_capacity(result_size);
-        let all_pixels = self.to_gray().get_channel(0);
-        let kernel_values = kernel.flatten();
+        let scaling = Scaling::new(255.0/kernel.max_response(), 0.0).absolute();
+        FloatMat::new(new_cols, new_rows, 1, response).to_mat(&scaling)
+    }
 
-        let pixels = &all_pixels.pixels;
-        let mut unified_pixels = Vec::<f32>::with_capacity(pixels.len());
-        
-        for pixel in pixels {
-            unified_pixels.push(*pixel as f32/255.0);
-        }
+    // Convolution of every channel that keeps the image size, outside
+    // samples are read through `border`. Results are rounded and clamped to
+    // 0-255.
+    pub fn convolute_border(&self, kernel: &kernels::Kernel, border: kernels::BorderMode) -> Mat {
+        self.convolute_raw(kernel, border).to_mat(&Scaling::default())
+    }
 
-        for i in 0..unified_pixels.len() {
-            let indexes_result = kernel.indexes(i, self.cols as usize, unified_pixels.len());
-            if indexes_result.0 {
-                let mut point_result = 0f32;
-                
-                for i in 0..(kernel.elements()) {
-                    let pixel_index = indexes_result.1[i];
-                    point_result = unified_pixels[pixel_index] * kernel_values[i] + point_result;
-                }
-                
-                let pixel = ((point_result/4.0)*255.0).abs();
-                result_pixels.push(pixel as u8);
-            }
-        }
-        Mat::load_from_vec(result_pixels, new_cols as usize, new_rows as usize, 1)
+    pub fn convolute_border_with(&self, backend: &dyn Backend, kernel: &kernels::Kernel, border: kernels::BorderMode)
+        -> BackendResult<Mat>
+    {
+        Ok(self.convolute_raw_with(backend, kernel, border)?.to_mat(&Scaling::default()))
+    }
+
+    // `convolute_border` with `scaling` instead of plain saturation.
+    pub fn convolute_scaled(&self, kernel: &kernels::Kernel, border: kernels::BorderMode, scaling: &Scaling) -> Mat {
+        self.convolute_raw(kernel, border).to_mat(scaling)
+    }
+
+    // The unscaled, signed response of every channel on the 0-255 pixel
+    // values, the size kept.
+    pub fn convolute_raw(&self, kernel: &kernels::Kernel, border: kernels::BorderMode) -> FloatMat {
+        backend::run(|b| self.convolute_raw_with(b, kernel, border))
+    }
+
+    pub fn convolute_raw_with(&self, backend: &dyn Backend, kernel: &kernels::Kernel, border: kernels::BorderMode)
+        -> BackendResult<FloatMat>
+    {
+        let values = self.to_float().values;
+        let result = backend.convolute_border(&values, self.cols, self.rows, self.bytes_per_pixel, kernel, border)?;
+        Ok(FloatMat::new(self.cols, self.rows, self.bytes_per_pixel, result))
     }
 
     pub fn fast_search_features(&self, threshold: usize, mask: &(usize, usize, usize, usize), direction: Direction)
         -> Vec<PixelDescription>
-    {   
-        let mut descriptions = Vec::<PixelDescription>::new();
-        let now = Instant::now();
-        
-        for y in (mask.1)..(mask.1+mask.3) {
-            for x in (mask.0)..(mask.0+mask.2) {
-                let (result, mut description) = PixelDescription::load_as_fast((x, y), self, threshold, &direction);
-                if result {
-                    descriptions.push(description);
-                }
-            }   
-        }
-        println!("");
-        println!("Spend ms on generate descriptions: {:?}", now.elapsed().as_millis());
+    {
+        self.fast_search_features_with(backend::default_backend(), threshold, mask, direction, &LogObserver)
+    }
+
+    // Same as `fast_search_features` on `backend`, reporting the detection,
+    // NMS and descriptor stages to `observer`.
+    pub fn fast_search_features_with(
+        &self,
+        backend: &dyn Backend,
+        threshold: usize,
+        mask: &(usize, usize, usize, usize),
+        direction: Direction,
+        observer: &dyn Observer
+    ) -> Vec<PixelDescription> {
+        let mut descriptions = timed(observer, "features.detect", || {
+            backend::run_on(backend, |b| self.fast_candidates_with(b, threshold, mask))
+        });
 
-        let before_nms = Instant::now();
-        descriptions = self.nms(&mut descriptions);
-        println!("Spend ms on NMS:{}", before_nms.elapsed().as_millis());
-        let len = descriptions.len();
-        
-        let before_calculate_pair = Instant::now();
-        for i in 0..descriptions.len() {
-            descriptions[i].calculate_pair(self, &direction);
-        }
-        println!("Spend ms on calculate pairs:{}", before_calculate_pair.elapsed().as_millis());
+        descriptions = timed(observer, "features.nms", || self.nms(&mut descriptions));
+
+        timed(observer, "features.describe", || self.describe_with(backend, &mut descriptions, &direction));
 
-        println!("Feature points:{:?}", len);
+        debug!("Feature points:{:?}", descriptions.len());
         descriptions
     }
 
@@ -344,36 +399,80 @@ impl Mat {
     fn nms(&self, descriptions: &mut Vec<PixelDescription>)
         -> Vec<PixelDescription>
     {
-        let window_size = 5;
-        let r = window_size/2;
-        println!("R = {:?}", r);
-
-        let mut current_descriptions = Vec::<PixelDescription>::new();
-        let len = descriptions.len();
-        println!("Total descriptions {:?}", len);
-        for desc_i in 0..len {
-            for other_i in 0..len {
-                if descriptions[other_i].coordinate != descriptions[desc_i].coordinate {
-                    let xr = ((descriptions[desc_i].coordinate.0 as i32 - descriptions[other_i].coordinate.0 as i32)).abs();
-                    let yr = ((descriptions[desc_i].coordinate.1 as i32 - descriptions[other_i].coordinate.1 as i32)).abs();
-                    if (xr <= r) && (yr <= r) {
-                        if descriptions[desc_i].maximum_value() >= descriptions[other_i].maximum_value() {
-                            descriptions[other_i].remove();
-                        }
-                    }
-                }
-            }
+        let options = nms::NmsOptions::default();
+        let current_descriptions = nms::suppress(descriptions, &options);
+        trace!("NMS kept {:?} of {:?} descriptions", current_descriptions.len(), descriptions.len());
+
+        current_descriptions
+    }
+
+    // FAST keypoints inside `mask`, the same as calling
+    // `PixelDescription::load_as_fast` on each of its pixels.
+    pub fn fast_candidates(&self, threshold: usize, mask: &Rect) -> Vec<PixelDescription> {
+        backend::run(|b| self.fast_candidates_with(b, threshold, mask))
+    }
+
+    pub fn fast_candidates_with(&self, backend: &dyn Backend, threshold: usize, mask: &Rect) -> BackendResult<Vec<PixelDescription>> {
+        let first_channel = self.first_channel();
+        let scores = backend.fast_scores(&first_channel, self.cols, self.rows, mask, threshold)?;
+
+        let mut descriptions = Vec::<PixelDescription>::new();
+        for (i, score) in scores.iter().enumerate().filter(|(_, score)| **score > 0) {
+            let (x, y) = (mask.0 + i%mask.2, mask.1 + i/mask.2);
+            let value = first_channel[y*self.cols + x];
+            let mut description = PixelDescription::new();
+            description.coordinate = (x, y);
+            description.description = fast::CIRCLE.iter().map(|(dx, dy)| {
+                let index = (y as i32 + dy) as usize*self.cols + (x as i32 + dx) as usize;
+                first_channel[index] as i16 - value as i16
+            }).collect();
+            description.descriptor = BinaryDescriptor::from_pairs(&[0u8]);
+            description.value = value;
+            description.removed = false;
+            description.response = *score as f32;
+            descriptions.push(description);
         }
+        Ok(descriptions)
+    }
 
-        for desc in descriptions {
-            // println!("{:?}", desc.removed);
-            if !(desc.removed) {
-                current_descriptions.push(desc.clone());
+    // BRIEF descriptors of all `descriptions` in one backend call, the same
+    // as `PixelDescription::calculate_pair` on each of them.
+    pub fn describe(&self, descriptions: &mut [PixelDescription], direction: &Direction) {
+        self.describe_with(backend::default_backend(), descriptions, direction)
+    }
+
+    pub fn describe_with(&self, backend: &dyn Backend, descriptions: &mut [PixelDescription], direction: &Direction) {
+        // Oriented keypoints each get their own steered pattern.
+        let offsets: Vec<brief_pattern::Offsets> = match direction {
+            Direction::Oriented => descriptions.iter_mut().flat_map(|description| {
+                description.calculate_orientation(self);
+                brief_pattern::rotated(description.orientation)
+            }).collect(),
+            _ => brief_pattern::offsets(direction, 0.0).into_owned()
+        };
+        let keypoints: Vec<(i32, i32)> = descriptions.iter()
+            .map(|description| (description.coordinate.0 as i32, description.coordinate.1 as i32))
+            .collect();
+        let first_channel = self.first_channel();
+
+        let pairs = brief_pattern::PAIRS;
+        let tests = backend::run_on(backend, |b| b.calculate_pairs(&first_channel, self.cols, self.rows, &keypoints, &offsets, pairs));
+        for (description, tests) in descriptions.iter_mut().zip(tests.chunks(pairs)) {
+            let mut descriptor = BinaryDescriptor::new();
+            for test in tests.iter().filter(|test| **test != 2) {
+                descriptor.push(*test == 1);
             }
+            description.descriptor = descriptor;
         }
-        println!("Effective descriptions {:?}", current_descriptions.len());
+    }
 
-        current_descriptions
+    // The first channel of every pixel; FAST and BRIEF only look at it.
+    fn first_channel(&self) -> Cow<'_, [u8]> {
+        if self.bytes_per_pixel == 1 {
+            Cow::Borrowed(&self.pixels)
+        } else {
+            Cow::Owned(self.pixels.iter().step_by(self.bytes_per_pixel).cloned().collect())
+        }
     }
 
     pub fn draw_point(&mut self, coordinate: (usize, usize), color: Vec<u8>) {
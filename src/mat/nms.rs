@@ -0,0 +1,218 @@
+use std::cmp::Ordering;
+
+use super::pixel_description::PixelDescription;
+
+#[derive(Debug, Clone, Copy)]
+pub enum NmsMode {
+    // Only the radius suppression.
+    Radius,
+    // Adaptive NMS: keep the `n` keypoints that are the strongest within the
+    // largest distance, which spreads them over the image.
+    Adaptive { n: usize },
+    // Split the keypoints' bounding box into `cols` x `rows` buckets and keep
+    // the `per_bucket` strongest keypoints of each.
+    Bucketed { cols: usize, rows: usize, per_bucket: usize },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NmsOptions {
+    // A keypoint is dropped when another one at most `radius` pixels away on
+    // both axes has an equal or greater response. 0 disables it.
+    pub radius: usize,
+    pub mode: NmsMode,
+    // Keep at most this many keypoints, strongest first, after `mode`.
+    pub retain: Option<usize>,
+}
+
+impl Default for NmsOptions {
+    fn default() -> NmsOptions {
+        NmsOptions { radius: 2, mode: NmsMode::Radius, retain: None }
+    }
+}
+
+// Uniform grid over the keypoints' bounding box, each cell lists the
+// indexes of the keypoints inside it.
+struct Grid {
+    origin: (usize, usize),
+    cell: usize,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl Grid {
+    fn new(descriptions: &[PixelDescription], cell: usize) -> Grid {
+        let cell = cell.max(1);
+        let min_x = descriptions.iter().map(|d| d.coordinate.0).min().unwrap_or(0);
+        let min_y = descriptions.iter().map(|d| d.coordinate.1).min().unwrap_or(0);
+        let max_x = descriptions.iter().map(|d| d.coordinate.0).max().unwrap_or(0);
+        let max_y = descriptions.iter().map(|d| d.coordinate.1).max().unwrap_or(0);
+        let cols = (max_x - min_x)/cell + 1;
+        let rows = (max_y - min_y)/cell + 1;
+        Grid { origin: (min_x, min_y), cell, cols, rows, cells: vec![Vec::new(); cols*rows] }
+    }
+
+    fn cell_of(&self, coordinate: (usize, usize)) -> (usize, usize) {
+        ((coordinate.0 - self.origin.0)/self.cell, (coordinate.1 - self.origin.1)/self.cell)
+    }
+
+    fn insert(&mut self, index: usize, coordinate: (usize, usize)) {
+        let (cx, cy) = self.cell_of(coordinate);
+        self.cells[cy*self.cols + cx].push(index);
+    }
+
+    // Indexes in the cells at ring distance `ring` around (cx, cy).
+    fn ring(&self, cx: usize, cy: usize, ring: usize) -> Vec<usize> {
+        let mut indexes = Vec::new();
+        let (cx, cy, ring) = (cx as i64, cy as i64, ring as i64);
+        for y in (cy - ring)..(cy + ring + 1) {
+            for x in (cx - ring)..(cx + ring + 1) {
+                let on_ring = (x - cx).abs() == ring || (y - cy).abs() == ring;
+                if !on_ring || x < 0 || y < 0 || x >= self.cols as i64 || y >= self.rows as i64 {
+                    continue;
+                }
+                indexes.extend_from_slice(&self.cells[(y as usize)*self.cols + x as usize]);
+            }
+        }
+        indexes
+    }
+}
+
+fn by_response_desc(a: &PixelDescription, b: &PixelDescription) -> Ordering {
+    b.response.partial_cmp(&a.response).unwrap_or(Ordering::Equal)
+}
+
+// Same result as comparing every pair, but only neighbouring grid cells
+// are visited. The input order is preserved, keypoints already marked as
+// removed still suppress their neighbours but are not returned.
+pub fn suppress_radius(descriptions: &[PixelDescription], radius: usize) -> Vec<PixelDescription> {
+    if radius == 0 || descriptions.is_empty() {
+        return descriptions.iter().filter(|d| !d.removed).cloned().collect();
+    }
+
+    let mut grid = Grid::new(descriptions, radius);
+    for (i, description) in descriptions.iter().enumerate() {
+        grid.insert(i, description.coordinate);
+    }
+
+    let r = radius as i64;
+    let mut kept = Vec::<PixelDescription>::new();
+    for description in descriptions {
+        let (cx, cy) = grid.cell_of(description.coordinate);
+        let mut suppressed = false;
+        for other_index in grid.ring(cx, cy, 0).into_iter().chain(grid.ring(cx, cy, 1)) {
+            let other = &descriptions[other_index];
+            if other.coordinate == description.coordinate {
+                continue;
+            }
+            let xr = (other.coordinate.0 as i64 - description.coordinate.0 as i64).abs();
+            let yr = (other.coordinate.1 as i64 - description.coordinate.1 as i64).abs();
+            if xr <= r && yr <= r && other.response >= description.response {
+                suppressed = true;
+                break;
+            }
+        }
+        if !suppressed && !description.removed {
+            kept.push(description.clone());
+        }
+    }
+    kept
+}
+
+pub fn adaptive(descriptions: &[PixelDescription], n: usize) -> Vec<PixelDescription> {
+    if descriptions.len() <= n {
+        return descriptions.to_vec();
+    }
+
+    let mut order: Vec<usize> = (0..descriptions.len()).collect();
+    order.sort_by(|a, b| by_response_desc(&descriptions[*a], &descriptions[*b]));
+
+    // Roughly one keypoint per cell keeps the ring searches short.
+    let min_x = descriptions.iter().map(|d| d.coordinate.0).min().unwrap();
+    let max_x = descriptions.iter().map(|d| d.coordinate.0).max().unwrap();
+    let min_y = descriptions.iter().map(|d| d.coordinate.1).min().unwrap();
+    let max_y = descriptions.iter().map(|d| d.coordinate.1).max().unwrap();
+    let area = ((max_x - min_x + 1)*(max_y - min_y + 1)) as f32;
+    let cell = (area/descriptions.len() as f32).sqrt().ceil() as usize;
+    let mut grid = Grid::new(descriptions, cell);
+
+    // Suppression radius: distance to the nearest keypoint that is at least
+    // as strong. Keypoints are inserted strongest first, so everything in
+    // the grid qualifies.
+    let mut radii = vec![0f32; descriptions.len()];
+    for (rank, &index) in order.iter().enumerate() {
+        let coordinate = descriptions[index].coordinate;
+        if rank == 0 {
+            radii[index] = f32::INFINITY;
+            grid.insert(index, coordinate);
+            continue;
+        }
+        let (cx, cy) = grid.cell_of(coordinate);
+        let mut best = f32::INFINITY;
+        let mut ring = 0;
+        loop {
+            // Everything in this ring or beyond is at least (ring - 1) cells away.
+            if ring > 0 && ((ring - 1)*grid.cell) as f32 > best {
+                break;
+            }
+            if ring > grid.cols.max(grid.rows) {
+                break;
+            }
+            for other in grid.ring(cx, cy, ring) {
+                let o = descriptions[other].coordinate;
+                let dx = o.0 as f32 - coordinate.0 as f32;
+                let dy = o.1 as f32 - coordinate.1 as f32;
+                best = best.min((dx*dx + dy*dy).sqrt());
+            }
+            ring += 1;
+        }
+        radii[index] = best;
+        grid.insert(index, coordinate);
+    }
+
+    // Order is already strongest first, a stable sort keeps that for ties.
+    order.sort_by(|a, b| radii[*b].partial_cmp(&radii[*a]).unwrap_or(Ordering::Equal));
+    order.iter().take(n).map(|i| descriptions[*i].clone()).collect()
+}
+
+pub fn bucketed(descriptions: &[PixelDescription], cols: usize, rows: usize, per_bucket: usize) -> Vec<PixelDescription> {
+    if descriptions.is_empty() {
+        return Vec::new();
+    }
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    let min_x = descriptions.iter().map(|d| d.coordinate.0).min().unwrap();
+    let max_x = descriptions.iter().map(|d| d.coordinate.0).max().unwrap();
+    let min_y = descriptions.iter().map(|d| d.coordinate.1).min().unwrap();
+    let max_y = descriptions.iter().map(|d| d.coordinate.1).max().unwrap();
+    let bucket_w = (max_x - min_x)/cols + 1;
+    let bucket_h = (max_y - min_y)/rows + 1;
+
+    let mut buckets = vec![Vec::<usize>::new(); cols*rows];
+    for (i, description) in descriptions.iter().enumerate() {
+        let bx = (description.coordinate.0 - min_x)/bucket_w;
+        let by = (description.coordinate.1 - min_y)/bucket_h;
+        buckets[by*cols + bx].push(i);
+    }
+
+    let mut kept = Vec::<PixelDescription>::new();
+    for bucket in buckets.iter_mut() {
+        bucket.sort_by(|a, b| by_response_desc(&descriptions[*a], &descriptions[*b]));
+        for i in bucket.iter().take(per_bucket) {
+            kept.push(descriptions[*i].clone());
+        }
+    }
+    kept
+}
+
+pub fn suppress(descriptions: &[PixelDescription], options: &NmsOptions) -> Vec<PixelDescription> {
+    let suppressed = suppress_radius(descriptions, options.radius);
+    let mut kept = match options.mode {
+        NmsMode::Radius => suppressed,
+        NmsMode::Adaptive { n } => adaptive(&suppressed, n),
+        NmsMode::Bucketed { cols, rows, per_bucket } => bucketed(&suppressed, cols, rows, per_bucket)
+    };
+    if let Some(n) = options.retain {
+        PixelDescription::retain_best(&mut kept, n);
+    }
+    kept
+}
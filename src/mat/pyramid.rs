@@ -0,0 +1,101 @@
+use super::Mat;
+use super::pixel_description::{PixelDescription, Direction};
+use crate::backend::{self, Backend};
+use crate::progress::{LogObserver, Observer};
+
+// Levels smaller than this on either side are not worth searching: the
+// BRIEF pattern alone spans more than 100 pixels.
+const MIN_LEVEL_SIZE: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Pyramid {
+    pub levels: Vec<Mat>,
+    pub scale_factor: f32,
+}
+
+impl Pyramid {
+    // Level 0 is `src` itself, every following level is smoothed and shrunk
+    // by `scale_factor` (> 1.0). Building stops early once a level would get
+    // too small.
+    pub fn new(src: &Mat, scale_factor: f32, levels: usize)
+        -> Pyramid
+    {
+        if scale_factor <= 1.0 {
+            panic!("Pyramid scale factor should be greater than 1.0, but it is {}", scale_factor);
+        }
+
+        let mut mats = Vec::<Mat>::with_capacity(levels);
+        mats.push(src.clone());
+
+        // Blur just enough to suppress aliasing for the given reduction.
+        let sigma = (scale_factor*scale_factor - 1.0).sqrt();
+
+        for level in 1..levels {
+            let scale = scale_factor.powi(level as i32);
+            let width = (src.cols as f32/scale).round() as usize;
+            let height = (src.rows as f32/scale).round() as usize;
+            if width < MIN_LEVEL_SIZE || height < MIN_LEVEL_SIZE {
+                break;
+            }
+            let smoothed = mats[level - 1].gaussian_blur(sigma);
+            mats.push(smoothed.resize(width, height));
+        }
+
+        Pyramid { levels: mats, scale_factor }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    // Factor from level coordinates back to level 0.
+    pub fn scale(&self, level: usize) -> f32 {
+        self.levels[0].cols as f32/self.levels[level].cols as f32
+    }
+
+    // Runs `fast_search_features` on every level. The mask is given in level 0
+    // coordinates; descriptors are computed on their own level and the
+    // coordinates of the returned keypoints are mapped back to level 0.
+    pub fn fast_search_features(&self, threshold: usize, mask: &(usize, usize, usize, usize), direction: Direction)
+        -> Vec<PixelDescription>
+    {
+        self.fast_search_features_with(backend::default_backend(), threshold, mask, direction, &LogObserver)
+    }
+
+    pub fn fast_search_features_with(
+        &self,
+        backend: &dyn Backend,
+        threshold: usize,
+        mask: &(usize, usize, usize, usize),
+        direction: Direction,
+        observer: &dyn Observer
+    ) -> Vec<PixelDescription> {
+        let mut descriptions = Vec::<PixelDescription>::new();
+
+        for (level, mat) in self.levels.iter().enumerate() {
+            let scale = self.scale(level);
+            let x = ((mask.0 as f32/scale).round() as usize).min(mat.cols);
+            let y = ((mask.1 as f32/scale).round() as usize).min(mat.rows);
+            let w = ((mask.2 as f32/scale).round() as usize).min(mat.cols - x);
+            let h = ((mask.3 as f32/scale).round() as usize).min(mat.rows - y);
+
+            for mut description in mat.fast_search_features_with(backend, threshold, &(x, y, w, h), direction, observer) {
+                let coordinate = description.coordinate;
+                description.coordinate = (
+                    ((coordinate.0 as f32*scale).round() as usize).min(self.levels[0].cols - 1),
+                    ((coordinate.1 as f32*scale).round() as usize).min(self.levels[0].rows - 1)
+                );
+                description.octave = level;
+                description.scale = scale;
+                descriptions.push(description);
+            }
+            observer.progress("pyramid", level + 1, self.levels.len());
+        }
+
+        descriptions
+    }
+}
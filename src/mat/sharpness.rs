@@ -0,0 +1,130 @@
+use super::Mat;
+use super::float_mat::FloatMat;
+use super::kernels::{BorderMode, Kernel};
+
+// Focus measures, all computed on the gray image in 0-255 units. Higher is
+// sharper; values are only comparable between images of the same scene and
+// method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharpnessMethod {
+    // Variance of the 4-neighbour Laplacian response.
+    VarianceOfLaplacian,
+    // Mean squared Sobel gradient magnitude.
+    Tenengrad,
+    // Mean squared difference between pixels two columns apart.
+    Brenner,
+    // Mean of |d²/dx²| + |d²/dy²|, which cannot cancel out like the
+    // Laplacian can.
+    ModifiedLaplacian,
+}
+
+// Sharpness of a grid of tiles, row by row. Tiles at the right and bottom
+// edges take the pixels left over by the division.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharpnessMap {
+    pub method: SharpnessMethod,
+    pub cols: usize,
+    pub rows: usize,
+    pub tile_width: usize,
+    pub tile_height: usize,
+    pub values: Vec<f32>,
+}
+
+impl SharpnessMap {
+    pub fn get(&self, col: usize, row: usize) -> f32 {
+        self.values[row*self.cols + col]
+    }
+
+    // (x, y, width, height) of a tile in the image.
+    pub fn tile_rect(&self, col: usize, row: usize, image_cols: usize, image_rows: usize) -> (usize, usize, usize, usize) {
+        let x = col*self.tile_width;
+        let y = row*self.tile_height;
+        let width = if col + 1 == self.cols { image_cols - x } else { self.tile_width };
+        let height = if row + 1 == self.rows { image_rows - y } else { self.tile_height };
+        (x, y, width, height)
+    }
+
+    pub fn min(&self) -> f32 {
+        self.values.iter().cloned().fold(f32::MAX, f32::min)
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values.iter().cloned().fold(f32::MIN, f32::max)
+    }
+
+    pub fn mean(&self) -> f32 {
+        self.values.iter().sum::<f32>()/self.values.len().max(1) as f32
+    }
+}
+
+// The per-pixel response a method aggregates.
+fn response(gray: &Mat, method: SharpnessMethod) -> FloatMat {
+    let border = BorderMode::Reflect;
+    match method {
+        SharpnessMethod::VarianceOfLaplacian => gray.convolute_raw(&Kernel::laplation_4(), border),
+        SharpnessMethod::Tenengrad => {
+            let gx = gray.convolute_raw(&Kernel::sobel_x(), border);
+            let gy = gray.convolute_raw(&Kernel::sobel_y(), border);
+            let values = gx.values.iter().zip(gy.values.iter()).map(|(x, y)| x*x + y*y).collect();
+            FloatMat::new(gray.cols, gray.rows, 1, values)
+        },
+        SharpnessMethod::Brenner => {
+            let kernel = Kernel::from_values(3, 1, &[-1.0, 0.0, 1.0]).with_anchor(0, 0);
+            let difference = gray.convolute_raw(&kernel, border);
+            let values = difference.values.iter().map(|d| d*d).collect();
+            FloatMat::new(gray.cols, gray.rows, 1, values)
+        },
+        SharpnessMethod::ModifiedLaplacian => {
+            let dxx = gray.convolute_raw(&Kernel::from_values(3, 1, &[-1.0, 2.0, -1.0]), border);
+            let dyy = gray.convolute_raw(&Kernel::from_values(1, 3, &[-1.0, 2.0, -1.0]), border);
+            let values = dxx.values.iter().zip(dyy.values.iter()).map(|(x, y)| x.abs() + y.abs()).collect();
+            FloatMat::new(gray.cols, gray.rows, 1, values)
+        }
+    }
+}
+
+// Aggregate of the response inside `rect`: the variance for the Laplacian,
+// the mean for the others.
+fn aggregate(response: &FloatMat, method: SharpnessMethod, rect: (usize, usize, usize, usize)) -> f32 {
+    let (x, y, width, height) = rect;
+    let count = (width*height) as f64;
+    if count == 0.0 {
+        return 0.0;
+    }
+    let values = || (y..(y + height)).flat_map(move |row| (x..(x + width)).map(move |col| response.get(col, row, 0) as f64));
+    let mean = values().sum::<f64>()/count;
+    match method {
+        SharpnessMethod::VarianceOfLaplacian => (values().map(|v| (v - mean).powi(2)).sum::<f64>()/count) as f32,
+        _ => mean as f32
+    }
+}
+
+impl Mat {
+    pub fn sharpness(&self, method: SharpnessMethod) -> f32 {
+        let response = response(&self.gray(), method);
+        aggregate(&response, method, (0, 0, self.cols, self.rows))
+    }
+
+    // Sharpness of `cols` x `rows` tiles, to find the blurry parts of an
+    // image. Tiles are at least one pixel wide and high.
+    pub fn sharpness_map(&self, method: SharpnessMethod, cols: usize, rows: usize) -> SharpnessMap {
+        let cols = cols.max(1).min(self.cols.max(1));
+        let rows = rows.max(1).min(self.rows.max(1));
+        let response = response(&self.gray(), method);
+        let mut map = SharpnessMap {
+            method,
+            cols,
+            rows,
+            tile_width: self.cols/cols,
+            tile_height: self.rows/rows,
+            values: Vec::with_capacity(cols*rows),
+        };
+        for row in 0..rows {
+            for col in 0..cols {
+                let rect = map.tile_rect(col, row, self.cols, self.rows);
+                map.values.push(aggregate(&response, method, rect));
+            }
+        }
+        map
+    }
+}
@@ -0,0 +1,236 @@
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::Mat;
+use super::fft::Spectrum;
+
+// (from, to) point correspondence.
+pub type PointPair = ((f32, f32), (f32, f32));
+
+// Maps (x, y) to (a*x + b*y + tx, c*x + d*y + ty).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Affine {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+// Gaussian weight of the cross-power spectrum in `Mat::phase_correlate`, in
+// cycles per pixel.
+pub const PHASE_CORRELATION_CUTOFF: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhaseCorrelation {
+    // (dx, dy) so that other(x, y) ~ self(x - dx, y - dy).
+    pub shift: (f32, f32),
+    // Peak height, close to 1 for shifted copies and below 0.1 for
+    // unrelated images.
+    pub response: f32,
+}
+
+impl Default for Affine {
+    fn default() -> Affine {
+        Affine::identity()
+    }
+}
+
+// Solves the 3x3 system m * p = v, None when it is singular.
+fn solve_3x3(m: [[f64; 3]; 3], v: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0]*(m[1][1]*m[2][2] - m[1][2]*m[2][1])
+            - m[0][1]*(m[1][0]*m[2][2] - m[1][2]*m[2][0])
+            + m[0][2]*(m[1][0]*m[2][1] - m[1][1]*m[2][0])
+    };
+    let determinant = det(&m);
+    if determinant.abs() < 1e-9 {
+        return None;
+    }
+    // Cramer's rule.
+    let mut p = [0f64; 3];
+    for (column, value) in p.iter_mut().enumerate() {
+        let mut replaced = m;
+        for row in 0..3 {
+            replaced[row][column] = v[row];
+        }
+        *value = det(&replaced)/determinant;
+    }
+    Some(p)
+}
+
+impl Affine {
+    pub fn identity() -> Affine {
+        Affine { a: 1.0, b: 0.0, c: 0.0, d: 1.0, tx: 0.0, ty: 0.0 }
+    }
+
+    pub fn translation(tx: f32, ty: f32) -> Affine {
+        Affine { tx, ty, ..Affine::identity() }
+    }
+
+    pub fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+        (self.a*x + self.b*y + self.tx, self.c*x + self.d*y + self.ty)
+    }
+
+    pub fn inverse(&self) -> Option<Affine> {
+        let det = self.a*self.d - self.b*self.c;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let (a, b, c, d) = (self.d/det, -self.b/det, -self.c/det, self.a/det);
+        Some(Affine { a, b, c, d, tx: -(a*self.tx + b*self.ty), ty: -(c*self.tx + d*self.ty) })
+    }
+
+    // Least squares fit of `to = transform(from)` over all pairs, None with
+    // fewer than three pairs or when they are collinear.
+    pub fn fit(pairs: &[PointPair]) -> Option<Affine> {
+        if pairs.len() < 3 {
+            return None;
+        }
+        // Normal equations, shared by both output coordinates.
+        let mut m = [[0f64; 3]; 3];
+        let mut vx = [0f64; 3];
+        let mut vy = [0f64; 3];
+        for &((x, y), (to_x, to_y)) in pairs {
+            let row = [x as f64, y as f64, 1.0];
+            for i in 0..3 {
+                for j in 0..3 {
+                    m[i][j] += row[i]*row[j];
+                }
+                vx[i] += row[i]*to_x as f64;
+                vy[i] += row[i]*to_y as f64;
+            }
+        }
+        let px = solve_3x3(m, vx)?;
+        let py = solve_3x3(m, vy)?;
+        Some(Affine {
+            a: px[0] as f32, b: px[1] as f32, tx: px[2] as f32,
+            c: py[0] as f32, d: py[1] as f32, ty: py[2] as f32,
+        })
+    }
+
+    // RANSAC over minimal three pair samples, refitted on the inliers of the
+    // best sample. Pairs are inliers when the transform lands within
+    // `threshold` pixels of their target. Seeded, so results repeat.
+    pub fn ransac(pairs: &[PointPair], threshold: f32, iterations: usize) -> Option<(Affine, Vec<usize>)> {
+        if pairs.len() < 3 {
+            return None;
+        }
+        let inliers_of = |transform: &Affine| -> Vec<usize> {
+            pairs.iter().enumerate().filter(|(_, &((x, y), (to_x, to_y)))| {
+                let (px, py) = transform.apply(x, y);
+                (px - to_x).hypot(py - to_y) <= threshold
+            }).map(|(i, _)| i).collect()
+        };
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut best = Vec::<usize>::new();
+        for _ in 0..iterations {
+            let sample: Vec<_> = rand::seq::index::sample(&mut rng, pairs.len(), 3).iter().map(|i| pairs[i]).collect();
+            if let Some(transform) = Affine::fit(&sample) {
+                let inliers = inliers_of(&transform);
+                if inliers.len() > best.len() {
+                    best = inliers;
+                }
+            }
+            if best.len() == pairs.len() {
+                break;
+            }
+        }
+
+        let inlier_pairs: Vec<_> = best.iter().map(|i| pairs[*i]).collect();
+        let transform = Affine::fit(&inlier_pairs)?;
+        let inliers = inliers_of(&transform);
+        Some((transform, inliers))
+    }
+}
+
+impl Mat {
+    // Output pixel (x, y) is this image sampled bilinearly at
+    // `transform(x, y)`; samples outside read the nearest edge pixel.
+    pub fn warp_affine(&self, transform: &Affine, width: usize, height: usize) -> Mat {
+        let channels = self.bytes_per_pixel;
+        let mut pixels = Vec::<u8>::with_capacity(width*height*channels);
+        if self.cols == 0 || self.rows == 0 {
+            pixels.resize(width*height*channels, 0);
+            return Mat::load_from_vec(pixels, width, height, channels);
+        }
+        for y in 0..height {
+            for x in 0..width {
+                let (src_x, src_y) = transform.apply(x as f32, y as f32);
+                let src_x = src_x.clamp(0.0, (self.cols - 1) as f32);
+                let src_y = src_y.clamp(0.0, (self.rows - 1) as f32);
+                let (x0, y0) = (src_x.floor() as usize, src_y.floor() as usize);
+                let (x1, y1) = ((x0 + 1).min(self.cols - 1), (y0 + 1).min(self.rows - 1));
+                let (fx, fy) = (src_x - x0 as f32, src_y - y0 as f32);
+                for chn in 0..channels {
+                    let value = |xx: usize, yy: usize| self.pixels[(yy*self.cols + xx)*channels + chn] as f32;
+                    let top = value(x0, y0)*(1.0 - fx) + value(x1, y0)*fx;
+                    let bottom = value(x0, y1)*(1.0 - fx) + value(x1, y1)*fx;
+                    pixels.push((top*(1.0 - fy) + bottom*fy).round() as u8);
+                }
+            }
+        }
+        Mat::load_from_vec(pixels, width, height, channels)
+    }
+
+    // Translation (dx, dy) of `other` relative to this image, so that
+    // other(x, y) ~ self(x - dx, y - dy), see `phase_correlation`.
+    pub fn phase_correlate(&self, other: &Mat) -> (f32, f32) {
+        self.phase_correlation(other, PHASE_CORRELATION_CUTOFF).shift
+    }
+
+    // Phase correlation of the gray images with sub-pixel peak interpolation.
+    // Both must have the same size. Normalising the cross-power spectrum
+    // makes every frequency count the same, so the fine detail that only one
+    // frame kept, e.g. when they are focused at different depths, would bury
+    // the peak in noise. The normalised spectrum is therefore weighted by a
+    // Gaussian of `cutoff` cycles per pixel, favouring the coarse structure
+    // both images share.
+    pub fn phase_correlation(&self, other: &Mat, cutoff: f32) -> PhaseCorrelation {
+        if self.cols != other.cols || self.rows != other.rows {
+            panic!("Phase correlation needs images of the same size");
+        }
+        let (cols, rows) = (self.cols, self.rows);
+        // A Hann window keeps the image edges from dominating the spectrum.
+        // The mean is removed first, or the window itself would correlate
+        // at a zero shift.
+        let hann = |i: usize, n: usize| 0.5 - 0.5*(2.0*std::f32::consts::PI*i as f32/n.max(2) as f32).cos();
+        let windowed = |mat: &Mat| -> Vec<f32> {
+            let gray = mat.gray();
+            let mean = gray.pixels.iter().map(|p| *p as f32).sum::<f32>()/gray.pixels.len().max(1) as f32;
+            gray.pixels.iter().enumerate().map(|(i, p)| (*p as f32 - mean)*hann(i%cols, cols)*hann(i/cols, rows)).collect()
+        };
+        let first = Spectrum::from_real(&windowed(self), cols, rows);
+        let second = Spectrum::from_real(&windowed(other), cols, rows);
+
+        let frequency = |i: usize, n: usize| i.min(n - i) as f32/n as f32;
+        let weight = |u: usize, v: usize| (-0.5*(frequency(u, cols).hypot(frequency(v, rows))/cutoff).powi(2)).exp();
+        let mut cross = Spectrum { cols, rows, data: first.data.clone() };
+        cross.filter(&|u, v, a| {
+            let product = second.get(u, v)*a.conj();
+            let norm = product.norm();
+            if norm > 1e-12 { product.scale(weight(u, v)/norm) } else { product }
+        });
+        let surface = cross.inverse();
+        // Height of the peak a shifted copy would give.
+        let total: f32 = (0..rows).flat_map(|v| (0..cols).map(move |u| (u, v))).map(|(u, v)| weight(u, v)).sum();
+
+        let peak = surface.iter().enumerate().fold(0, |best, (i, v)| if *v > surface[best] { i } else { best });
+        let (px, py) = (peak%cols, peak/cols);
+        let at = |x: usize, y: usize| surface[(y%rows)*cols + x%cols];
+        // Parabola through the peak and its neighbours on each axis.
+        let refine = |before: f32, center: f32, after: f32| {
+            let denominator = before - 2.0*center + after;
+            if denominator.abs() > 1e-12 { (0.5*(before - after)/denominator).clamp(-0.5, 0.5) } else { 0.0 }
+        };
+        let sub_x = refine(at(px + cols - 1, py), at(px, py), at(px + 1, py));
+        let sub_y = refine(at(px, py + rows - 1), at(px, py), at(px, py + 1));
+        let wrap = |p: usize, n: usize| if p > n/2 { p as f32 - n as f32 } else { p as f32 };
+        PhaseCorrelation {
+            shift: (wrap(px, cols) + sub_x, wrap(py, rows) + sub_y),
+            response: if total > 0.0 { surface[peak]*(cols*rows) as f32/total } else { 0.0 },
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Receives progress and per-stage timings of long-running operations
+// (stitching, feature search). Both methods default to doing nothing.
+pub trait Observer {
+    // `done` of `total` steps of `operation` are finished.
+    fn progress(&self, _operation: &str, _done: usize, _total: usize) {}
+
+    // A stage of an operation finished after `elapsed`.
+    fn stage(&self, _name: &str, _elapsed: Duration) {}
+}
+
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+// Default observer: timings at debug level and progress at trace level.
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn progress(&self, operation: &str, done: usize, total: usize) {
+        trace!("{}: {}/{}", operation, done, total);
+    }
+
+    fn stage(&self, name: &str, elapsed: Duration) {
+        debug!("{} took {} ms", name, elapsed.as_millis());
+    }
+}
+
+// Keeps every stage timing, for collecting metrics.
+#[derive(Default)]
+pub struct TimingCollector {
+    stages: Mutex<Vec<(String, Duration)>>,
+}
+
+impl TimingCollector {
+    pub fn new() -> TimingCollector {
+        TimingCollector::default()
+    }
+
+    pub fn stages(&self) -> Vec<(String, Duration)> {
+        self.stages.lock().unwrap().clone()
+    }
+
+    // Total time per stage name, in order of first appearance.
+    pub fn totals(&self) -> Vec<(String, Duration)> {
+        let mut totals = Vec::<(String, Duration)>::new();
+        for (name, elapsed) in self.stages.lock().unwrap().iter() {
+            match totals.iter_mut().find(|(n, _)| n == name) {
+                Some(total) => total.1 += *elapsed,
+                None => totals.push((name.clone(), *elapsed))
+            }
+        }
+        totals
+    }
+}
+
+impl Observer for TimingCollector {
+    fn stage(&self, name: &str, elapsed: Duration) {
+        self.stages.lock().unwrap().push((name.to_string(), elapsed));
+    }
+}
+
+// Runs `f` and reports its duration as stage `name`.
+pub fn timed<T, F: FnOnce() -> T>(observer: &dyn Observer, name: &str, f: F) -> T {
+    let now = Instant::now();
+    let result = f();
+    observer.stage(name, now.elapsed());
+    result
+}
@@ -0,0 +1,53 @@
+use eva_lib::mat::Mat;
+use eva_lib::mat::blur_detection::{BlurKind, BlurOptions};
+
+// 4x4 blocks of pseudo random gray levels, detail everywhere. The levels are
+// hashed, so the pattern has no dominant direction.
+fn scene(cols: usize, rows: usize) -> Mat {
+    let level = |bx: usize, by: usize| {
+        let mut h = (bx as u32).wrapping_mul(73856093) ^ (by as u32).wrapping_mul(19349663);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0x5bd1e995);
+        (h ^ (h >> 15)) >> 24
+    };
+    let pixels = (0..cols*rows).map(|i| level((i%cols)/4, (i/cols)/4) as u8).collect();
+    Mat::load_from_vec(pixels, cols, rows, 1)
+}
+
+#[test]
+fn defocused_half_is_one_region() {
+    let (cols, rows) = (256, 192);
+    let sharp = scene(cols, rows);
+    let blurred = sharp.gaussian_blur(4.0);
+    // The right half is defocused and the top left tile is flat, which
+    // cannot be judged either way.
+    let pixels = (0..cols*rows).map(|i| {
+        if i%cols >= 128 { blurred.pixels[i] } else if i%cols < 32 && i/cols < 32 { 90 } else { sharp.pixels[i] }
+    }).collect();
+    let image = Mat::load_from_vec(pixels, cols, rows, 1);
+
+    let report = image.detect_blur(&BlurOptions { tile_size: 32, ..BlurOptions::default() });
+    assert_eq!((report.map.cols, report.map.rows), (8, 6));
+    for (tile, kind) in report.kinds.iter().enumerate() {
+        let (col, row) = (tile%8, tile/8);
+        if col >= 4 {
+            // The seam with the sharp half can pass for motion blur.
+            assert!(kind.is_blurred(), "tile ({}, {}) is {:?}", col, row, kind);
+        } else if (col, row) == (0, 0) {
+            assert_eq!(*kind, BlurKind::Flat, "tile ({}, {})", col, row);
+        } else {
+            assert_eq!(*kind, BlurKind::Sharp, "tile ({}, {})", col, row);
+        }
+    }
+
+    assert_eq!(report.regions.len(), 1);
+    let region = &report.regions[0];
+    assert_eq!(region.rect, (128, 0, 128, 192));
+    assert_eq!(region.tiles, 24);
+
+    // Blurred tiles are the brightest, flat ones are not painted as sharp.
+    let heat = |x: usize, y: usize| report.heat_map.pixels[y*cols + x];
+    assert!(heat(200, 100) > 200);
+    assert!(heat(50, 100) < 50);
+    assert_eq!(heat(10, 10), 128);
+}
@@ -0,0 +1,122 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use eva_lib::mat::Mat;
+use eva_lib::mat::corners::{CornerScore, Detector};
+use eva_lib::mat::fast::{FastDetector, CIRCLE};
+
+fn circle(mat: &Mat, x: usize, y: usize) -> Option<(i32, Vec<i32>)> {
+    if x < 3 || y < 3 || x + 3 >= mat.cols || y + 3 >= mat.rows {
+        return None;
+    }
+    let center = mat.pixels[(y*mat.cols + x)*mat.bytes_per_pixel] as i32;
+    let circle = CIRCLE.iter().map(|(dx, dy)| {
+        let index = (y as i32 + dy) as usize*mat.cols + (x as i32 + dx) as usize;
+        mat.pixels[index*mat.bytes_per_pixel] as i32
+    }).collect();
+    Some((center, circle))
+}
+
+// Segment test straight from the definition: some run of `arc_length`
+// circle pixels, wrapping around, all brighter than centre + threshold or
+// all darker than centre - threshold.
+fn segment_test(mat: &Mat, x: usize, y: usize, arc_length: usize, threshold: i32) -> bool {
+    let (center, circle) = match circle(mat, x, y) {
+        Some(values) => values,
+        None => return false
+    };
+    (0..16).any(|start| {
+        let arc = || (0..arc_length).map(|i| circle[(start + i)%16]);
+        arc().all(|v| v > center + threshold) || arc().all(|v| v < center - threshold)
+    })
+}
+
+// Largest threshold from `threshold` up that still passes, 0 for no corner:
+// an arc passes every threshold below its smallest difference to the centre.
+fn brute_force_score(mat: &Mat, x: usize, y: usize, arc_length: usize, threshold: u8) -> u8 {
+    let (center, circle) = match circle(mat, x, y) {
+        Some(values) => values,
+        None => return 0
+    };
+    let best = (0..16).flat_map(|start| {
+        let arc: Vec<i32> = (0..arc_length).map(|i| circle[(start + i)%16]).collect();
+        let brighter = arc.iter().map(|v| v - center).min().unwrap() - 1;
+        let darker = arc.iter().map(|v| center - v).min().unwrap() - 1;
+        vec![brighter, darker]
+    }).max().unwrap();
+    if best >= threshold as i32 { best as u8 } else { 0 }
+}
+
+fn images() -> Vec<Mat> {
+    let mut rng = StdRng::seed_from_u64(33);
+    let noise = |rng: &mut StdRng, cols: usize, rows: usize, bytes_per_pixel: usize, range: u8| {
+        let pixels = (0..cols*rows*bytes_per_pixel).map(|_| rng.gen_range(0, range as u32 + 1) as u8).collect();
+        Mat::load_from_vec(pixels, cols, rows, bytes_per_pixel)
+    };
+    // Flat 3x3 blocks of random levels: their corners give long arcs.
+    let blocks = |rng: &mut StdRng, cols: usize, rows: usize| {
+        let levels: Vec<u8> = (0..(cols/3 + 1)*(rows/3 + 1)).map(|_| rng.gen()).collect();
+        let pixels = (0..cols*rows).map(|i| levels[(i/cols/3)*(cols/3 + 1) + (i%cols)/3]).collect();
+        Mat::load_from_vec(pixels, cols, rows, 1)
+    };
+    vec![
+        noise(&mut rng, 48, 40, 1, 255),
+        noise(&mut rng, 33, 29, 3, 120),
+        blocks(&mut rng, 60, 45),
+        blocks(&mut rng, 7, 7),
+    ]
+}
+
+#[test]
+fn detector_matches_the_brute_force_segment_test() {
+    let mut corners = 0;
+    for mat in images().iter() {
+        for arc_length in 9..13 {
+            for &threshold in &[0u8, 10, 20, 60] {
+                let detector = FastDetector::new(threshold, arc_length);
+                for y in 0..mat.rows {
+                    for x in 0..mat.cols {
+                        let expected = segment_test(mat, x, y, arc_length, threshold as i32);
+                        assert_eq!(detector.test(mat, (x, y)), expected, "({}, {}) arc {} threshold {}", x, y, arc_length, threshold);
+                        let score = if expected { brute_force_score(mat, x, y, arc_length, threshold) } else { 0 };
+                        assert_eq!(detector.corner_score(mat, (x, y)), score, "({}, {}) arc {} threshold {}", x, y, arc_length, threshold);
+                        assert_eq!(detector.score(mat, (x, y)), score as f32);
+                        corners += expected as usize;
+                    }
+                }
+            }
+        }
+    }
+    assert!(corners > 1000, "only {} corners, the images test too little", corners);
+}
+
+#[test]
+fn detect_lists_the_corners_inside_the_mask() {
+    let detector = FastDetector::default();
+    for mat in images().iter() {
+        let mask = (2, 1, mat.cols - 4, mat.rows - 2);
+        let found: Vec<(usize, usize)> = detector.detect(mat, &mask).iter().map(|d| d.coordinate).collect();
+        let mut expected = Vec::new();
+        for y in (mask.1)..(mask.1 + mask.3) {
+            for x in (mask.0)..(mask.0 + mask.2) {
+                if segment_test(mat, x, y, 9, 20) {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(found, expected);
+
+        for description in detector.detect(mat, &mask) {
+            let (x, y) = description.coordinate;
+            assert_eq!(description.response, brute_force_score(mat, x, y, 9, 20) as f32);
+            assert_eq!(description.value, mat.pixels[(y*mat.cols + x)*mat.bytes_per_pixel]);
+            assert_eq!(description.description.len(), 16);
+        }
+    }
+}
+
+#[test]
+#[should_panic]
+fn arc_lengths_outside_9_to_12_are_rejected() {
+    FastDetector::new(20, 8);
+}
@@ -0,0 +1,73 @@
+use eva_lib::mat::descriptor::BinaryDescriptor;
+use eva_lib::mat::feature_file;
+use eva_lib::mat::pixel_description::PixelDescription;
+
+// Keypoints with descriptors of every interesting length: empty, shorter
+// than a word, exactly one word, a partial last word and the full pattern.
+fn keypoints() -> Vec<PixelDescription> {
+    [0usize, 1, 63, 64, 65, 200, 256].iter().enumerate().map(|(i, &len)| {
+        let mut description = PixelDescription::new();
+        description.coordinate = (i*17, 1000 + i);
+        description.description = (0..16).map(|k| (k as i16 - 8)*(i as i16 + 1)).collect();
+        description.value = 40*i as u8;
+        description.removed = i%2 == 0;
+        description.orientation = -1.25 + i as f32;
+        description.octave = i%3;
+        description.scale = 1.2f32.powi((i%3) as i32);
+        description.response = 0.5*i as f32;
+        let tests: Vec<u8> = (0..len).map(|bit| ((bit*7 + i)%3 == 0) as u8).collect();
+        description.descriptor = BinaryDescriptor::from_pairs(&tests);
+        description
+    }).collect()
+}
+
+fn assert_same(loaded: &[PixelDescription], saved: &[PixelDescription]) {
+    assert_eq!(loaded.len(), saved.len());
+    for (loaded, saved) in loaded.iter().zip(saved.iter()) {
+        assert_eq!(loaded.coordinate, saved.coordinate);
+        assert_eq!(loaded.description, saved.description);
+        assert_eq!(loaded.value, saved.value);
+        assert_eq!(loaded.removed, saved.removed);
+        assert_eq!(loaded.descriptor, saved.descriptor);
+        assert_eq!(loaded.orientation, saved.orientation);
+        assert_eq!(loaded.octave, saved.octave);
+        assert_eq!(loaded.scale, saved.scale);
+        assert_eq!(loaded.response, saved.response);
+    }
+}
+
+#[test]
+fn json_round_trip() {
+    let saved = keypoints();
+    let loaded = feature_file::from_json(&feature_file::to_json(&saved)).unwrap();
+    assert_same(&loaded, &saved);
+}
+
+#[test]
+fn binary_round_trip() {
+    let saved = keypoints();
+    let mut bytes = Vec::<u8>::new();
+    feature_file::write_binary(&saved, &mut bytes).unwrap();
+    assert_eq!(&bytes[..4], b"EVAF");
+    assert_eq!(&bytes[4..8], &feature_file::VERSION.to_le_bytes());
+
+    let loaded = feature_file::read_binary(&mut bytes.as_slice()).unwrap();
+    assert_same(&loaded, &saved);
+}
+
+#[test]
+fn binary_rejects_bad_input() {
+    let mut bytes = Vec::<u8>::new();
+    feature_file::write_binary(&keypoints(), &mut bytes).unwrap();
+
+    let mut wrong_magic = bytes.clone();
+    wrong_magic[0] = b'X';
+    assert!(feature_file::read_binary(&mut wrong_magic.as_slice()).is_err());
+
+    let mut wrong_version = bytes.clone();
+    wrong_version[4..8].copy_from_slice(&(feature_file::VERSION + 1).to_le_bytes());
+    assert!(feature_file::read_binary(&mut wrong_version.as_slice()).is_err());
+
+    let truncated = &bytes[..bytes.len() - 3];
+    assert!(feature_file::read_binary(&mut &truncated[..]).is_err());
+}
@@ -0,0 +1,88 @@
+use eva_lib::backend::CpuBackend;
+use eva_lib::mat::Mat;
+use eva_lib::mat::pixel_description::{Direction, PixelDescription};
+
+// Deterministic blocky texture: flat 4x4 squares of pseudo random gray levels,
+// which gives plenty of FAST corners and BRIEF tests of both outcomes.
+fn textured(cols: usize, rows: usize, bytes_per_pixel: usize) -> Mat {
+    let level = |bx: usize, by: usize| ((bx*7919 + by*104729) as u32).wrapping_mul(2654435761) >> 24;
+    let pixels = (0..cols*rows).flat_map(|i| {
+        let value = level((i%cols)/4, (i/cols)/4) as u8;
+        (0..bytes_per_pixel).map(move |channel| value.wrapping_add(channel as u8*40))
+    }).collect();
+    Mat::load_from_vec(pixels, cols, rows, bytes_per_pixel)
+}
+
+// Bright 2x2 dots on a dark background every 8 pixels from the image edge,
+// blobs the legacy FAST test fires on.
+fn dots(cols: usize, rows: usize, bytes_per_pixel: usize) -> Mat {
+    let inside = |i: usize| i%8 < 2;
+    let pixels = (0..cols*rows).flat_map(|i| {
+        let value = if inside(i%cols) && inside(i/cols) { 200 } else { 30 };
+        (0..bytes_per_pixel).map(move |_| value)
+    }).collect();
+    Mat::load_from_vec(pixels, cols, rows, bytes_per_pixel)
+}
+
+#[test]
+fn describe_matches_per_keypoint_brief() {
+    for &bytes_per_pixel in &[1, 3] {
+        let mat = textured(97, 61, bytes_per_pixel);
+        // Inside, on and next to every edge and corner.
+        let coordinates = [(48, 30), (20, 40), (0, 0), (1, 2), (96, 60), (95, 0), (0, 59), (48, 0), (96, 30), (10, 58)];
+        for direction in &[Direction::Horizontal, Direction::Vertical, Direction::Oriented] {
+            let mut batched: Vec<PixelDescription> = coordinates.iter().map(|&coordinate| {
+                let mut description = PixelDescription::new();
+                description.coordinate = coordinate;
+                description
+            }).collect();
+            let mut single = batched.clone();
+
+            mat.describe_with(&CpuBackend, &mut batched, direction);
+            for description in single.iter_mut() {
+                description.calculate_pair(&mat, direction);
+            }
+            for (batched, single) in batched.iter().zip(single.iter()) {
+                assert_eq!(batched.descriptor, single.descriptor, "{:?} at {:?}", direction, batched.coordinate);
+                assert_eq!(batched.orientation, single.orientation);
+            }
+        }
+    }
+}
+
+#[test]
+fn fast_candidates_match_load_as_fast() {
+    for &bytes_per_pixel in &[1, 3] {
+        let mat = dots(83, 57, bytes_per_pixel);
+        let mut found = 0;
+        // The whole image, a mask touching the right and bottom edges, one
+        // splitting dots on its left and top edges and one too small to
+        // hold a circle.
+        let masks = [(0, 0, 83, 57), (60, 40, 23, 17), (9, 17, 30, 20), (0, 0, 4, 4)];
+        for mask in masks.iter() {
+            for &threshold in &[10, 40] {
+                let candidates = mat.fast_candidates_with(&CpuBackend, threshold, mask).unwrap();
+                let mut expected = Vec::<PixelDescription>::new();
+                for y in mask.1..(mask.1 + mask.3) {
+                    for x in mask.0..(mask.0 + mask.2) {
+                        let (found, description) = PixelDescription::load_as_fast((x, y), &mat, threshold, &Direction::Horizontal);
+                        if found {
+                            expected.push(description);
+                        }
+                    }
+                }
+
+                found += expected.len();
+                assert_eq!(candidates.len(), expected.len(), "mask {:?}, threshold {}", mask, threshold);
+                for (candidate, expected) in candidates.iter().zip(expected.iter()) {
+                    assert_eq!(candidate.coordinate, expected.coordinate);
+                    assert_eq!(candidate.description, expected.description);
+                    assert_eq!(candidate.value, expected.value);
+                    assert_eq!(candidate.response, expected.response);
+                    assert_eq!(candidate.removed, expected.removed);
+                }
+            }
+        }
+        assert!(found > 0);
+    }
+}
@@ -0,0 +1,76 @@
+use std::f64::consts::PI;
+
+use eva_lib::mat::Mat;
+use eva_lib::mat::fft::{self, Complex};
+use eva_lib::mat::kernels::Kernel;
+
+fn naive_dft(input: &[Complex], inverse: bool) -> Vec<(f64, f64)> {
+    let n = input.len();
+    let sign = if inverse { 1.0 } else { -1.0 };
+    (0..n).map(|k| {
+        input.iter().enumerate().fold((0.0, 0.0), |(re, im), (j, c)| {
+            let angle = sign*2.0*PI*((j*k)%n) as f64/n as f64;
+            let (sin, cos) = angle.sin_cos();
+            (re + c.re as f64*cos - c.im as f64*sin, im + c.re as f64*sin + c.im as f64*cos)
+        })
+    }).collect()
+}
+
+#[test]
+fn fft_matches_naive_dft() {
+    // Radix-2 lengths and Bluestein ones, prime or not.
+    for &n in &[1usize, 2, 3, 5, 6, 7, 8, 12, 17, 64, 100, 127] {
+        let input: Vec<Complex> = (0..n).map(|i| Complex::new(((i*7)%11) as f32 - 5.0, ((i*3)%5) as f32*0.5)).collect();
+        for &inverse in &[false, true] {
+            let mut output = input.clone();
+            fft::fft(&mut output, inverse);
+            let expected = naive_dft(&input, inverse);
+            let scale = (n as f64).sqrt()*5.0;
+            for (k, (found, expected)) in output.iter().zip(expected.iter()).enumerate() {
+                let error = (found.re as f64 - expected.0).hypot(found.im as f64 - expected.1);
+                assert!(error < 1e-4*scale, "n {} inverse {} bin {}: {:?} vs {:?}", n, inverse, k, found, expected);
+            }
+        }
+
+        // Forward then inverse gives the input back, n times larger.
+        let mut round_trip = input.clone();
+        fft::fft(&mut round_trip, false);
+        fft::fft(&mut round_trip, true);
+        for (found, original) in round_trip.iter().zip(input.iter()) {
+            assert!((found.re/n as f32 - original.re).abs() < 1e-3 && (found.im/n as f32 - original.im).abs() < 1e-3, "n {}", n);
+        }
+    }
+}
+
+#[test]
+fn fft_convolute_matches_convolute() {
+    let (cols, rows) = (45, 33);
+    let pixels = (0..cols*rows).map(|i| {
+        let h = (i as u32).wrapping_mul(2654435761);
+        (h ^ (h >> 16)) as u8
+    }).collect();
+    let mat = Mat::load_from_vec(pixels, cols, rows, 1);
+
+    for kernel in vec![Kernel::laplation_4(), Kernel::laplation_8(), Kernel::sobel_x(), Kernel::gaussian(2.0), Kernel::box_blur(9, 3)] {
+        let direct = mat.convolute(kernel.clone());
+        let transformed = mat.fft_convolute(kernel.clone());
+        assert_eq!((transformed.cols, transformed.rows), (cols - kernel.width() + 1, rows - kernel.height() + 1));
+        assert_eq!((transformed.cols, transformed.rows), (direct.cols, direct.rows));
+        for (a, b) in direct.pixels.iter().zip(transformed.pixels.iter()) {
+            assert!((*a as i32 - *b as i32).abs() <= 1, "{:?}", kernel.data);
+        }
+    }
+}
+
+#[test]
+fn kernels_larger_than_the_image_give_empty_results() {
+    let mat = Mat::load_from_vec(vec![10; 4*3], 4, 3, 1);
+    for kernel in vec![Kernel::gaussian(2.0), Kernel::box_blur(5, 1), Kernel::box_blur(1, 4)] {
+        assert!(mat.convolute(kernel.clone()).pixels.is_empty());
+        assert!(mat.fft_convolute(kernel.clone()).pixels.is_empty());
+        assert!(fft::fft_correlate(&[0.0; 12], 4, 3, &kernel).is_empty());
+    }
+    // A kernel exactly the image size leaves one pixel.
+    assert_eq!(mat.fft_convolute(Kernel::box_blur(4, 3)).pixels.len(), 1);
+    assert_eq!(mat.convolute(Kernel::box_blur(4, 3)).pixels.len(), 1);
+}
@@ -0,0 +1,103 @@
+use eva_lib::mat::Mat;
+use eva_lib::mat::kernels::Kernel;
+
+fn noisy(cols: usize, rows: usize, bytes_per_pixel: usize) -> Mat {
+    let pixels = (0..cols*rows*bytes_per_pixel).map(|i| {
+        let h = (i as u32).wrapping_mul(2654435761);
+        (h ^ (h >> 16)) as u8
+    }).collect();
+    Mat::load_from_vec(pixels, cols, rows, bytes_per_pixel)
+}
+
+// Channel `channel` of the pixel at (x, y), coordinates clamped to the image.
+fn clamped(mat: &Mat, x: i64, y: i64, channel: usize) -> u8 {
+    let x = x.clamp(0, mat.cols as i64 - 1) as usize;
+    let y = y.clamp(0, mat.rows as i64 - 1) as usize;
+    mat.pixels[(y*mat.cols + x)*mat.bytes_per_pixel + channel]
+}
+
+fn max_difference(a: &Mat, b: &[u8]) -> i32 {
+    assert_eq!(a.pixels.len(), b.len());
+    a.pixels.iter().zip(b.iter()).map(|(a, b)| (*a as i32 - *b as i32).abs()).max().unwrap_or(0)
+}
+
+fn each_sample<F: FnMut(i64, i64, usize)>(mat: &Mat, mut f: F) {
+    for y in 0..mat.rows as i64 {
+        for x in 0..mat.cols as i64 {
+            for channel in 0..mat.bytes_per_pixel {
+                f(x, y, channel);
+            }
+        }
+    }
+}
+
+#[test]
+fn median_filter_matches_sorting() {
+    for &(cols, rows, bytes_per_pixel, radius) in &[(23, 17, 1, 1), (23, 17, 3, 2), (31, 9, 1, 7), (5, 4, 3, 3), (12, 10, 1, 0)] {
+        let mat = noisy(cols, rows, bytes_per_pixel);
+        let r = radius as i64;
+        let mut expected = Vec::new();
+        each_sample(&mat, |x, y, channel| {
+            let mut window: Vec<u8> = (-r..=r).flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+                .map(|(dx, dy)| clamped(&mat, x + dx, y + dy, channel)).collect();
+            window.sort_unstable();
+            expected.push(window[window.len()/2]);
+        });
+        assert_eq!(mat.median_filter(radius).pixels, expected, "{}x{}x{} radius {}", cols, rows, bytes_per_pixel, radius);
+    }
+}
+
+#[test]
+fn gaussian_blur_matches_direct_sum() {
+    for &(bytes_per_pixel, sigma) in &[(1, 0.8), (3, 1.5), (1, 3.0)] {
+        let mat = noisy(29, 21, bytes_per_pixel);
+        let kernel = Kernel::gaussian(sigma);
+        let radius = (kernel.width()/2) as i64;
+        let mut expected = Vec::new();
+        each_sample(&mat, |x, y, channel| {
+            let mut sum = 0f64;
+            for (ky, row) in kernel.data.iter().enumerate() {
+                for (kx, weight) in row.iter().enumerate() {
+                    sum += *weight as f64*clamped(&mat, x + kx as i64 - radius, y + ky as i64 - radius, channel) as f64;
+                }
+            }
+            expected.push(sum.round().clamp(0.0, 255.0) as u8);
+        });
+        // The separable passes round differently from the direct sum.
+        assert!(max_difference(&mat.gaussian_blur(sigma), &expected) <= 1, "sigma {}", sigma);
+    }
+}
+
+#[test]
+fn bilateral_filter_matches_direct_sum() {
+    for &(bytes_per_pixel, radius, sigma_space, sigma_color) in &[(1, 2, 1.0, 30.0), (3, 3, 1.5, 50.0), (5, 1, 2.0, 10.0)] {
+        let mat = noisy(19, 14, bytes_per_pixel);
+        let r = radius as i64;
+        let channels = bytes_per_pixel;
+        let mut expected = Vec::new();
+        for y in 0..mat.rows as i64 {
+            for x in 0..mat.cols as i64 {
+                let mut sums = vec![0f64; channels];
+                let mut total = 0f64;
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        let (sx, sy) = (x + dx, y + dy);
+                        if dx*dx + dy*dy > r*r || sx < 0 || sy < 0 || sx >= mat.cols as i64 || sy >= mat.rows as i64 {
+                            continue;
+                        }
+                        let color: f64 = (0..channels).map(|c| (clamped(&mat, sx, sy, c) as f64 - clamped(&mat, x, y, c) as f64).powi(2)).sum();
+                        let distance = (dx*dx + dy*dy) as f64;
+                        let weight = (-distance/(2.0*sigma_space*sigma_space) - color/(2.0*sigma_color*sigma_color)).exp();
+                        total += weight;
+                        for (c, sum) in sums.iter_mut().enumerate() {
+                            *sum += weight*clamped(&mat, sx, sy, c) as f64;
+                        }
+                    }
+                }
+                expected.extend(sums.iter().map(|sum| (sum/total).round() as u8));
+            }
+        }
+        let filtered = mat.bilateral_filter(radius, sigma_space as f32, sigma_color as f32);
+        assert!(max_difference(&filtered, &expected) <= 1, "{} channels, radius {}", channels, radius);
+    }
+}
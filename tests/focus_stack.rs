@@ -0,0 +1,88 @@
+use eva_lib::mat::Mat;
+use eva_lib::mat::focus_stack::{focus_stack_with, Alignment, FocusStackOptions};
+use eva_lib::mat::transform::Affine;
+
+// Softened 6x6 blocks of pseudo random gray levels.
+fn scene(cols: usize, rows: usize) -> Mat {
+    let level = |bx: usize, by: usize| ((bx*7919 + by*104729) as u32).wrapping_mul(2654435761) >> 24;
+    let pixels = (0..cols*rows).map(|i| level((i%cols)/6, (i/cols)/6) as u8).collect();
+    Mat::load_from_vec(pixels, cols, rows, 1).gaussian_blur(1.0)
+}
+
+// `mat` with the left or the right half defocused.
+fn blur_half(mat: &Mat, left: bool, sigma: f32) -> Mat {
+    let blurred = mat.gaussian_blur(sigma);
+    let pixels = (0..mat.pixels.len()).map(|i| {
+        if (i%mat.cols < mat.cols/2) == left { blurred.pixels[i] } else { mat.pixels[i] }
+    }).collect();
+    Mat::load_from_vec(pixels, mat.cols, mat.rows, 1)
+}
+
+#[test]
+fn shifted_stack_with_mixed_focus() {
+    let (cols, rows) = (160, 120);
+    let sharp = scene(cols, rows);
+    // The second frame is the scene moved by (3, 2).
+    let shifted = sharp.warp_affine(&Affine::translation(-3.0, -2.0), cols, rows);
+    let frames = [blur_half(&sharp, false, 3.0), blur_half(&shifted, true, 3.0)];
+
+    for &alignment in &[Alignment::Translation, Alignment::Affine] {
+        let options = FocusStackOptions { alignment, ..FocusStackOptions::default() };
+        let stack = focus_stack_with(&frames, &options);
+        assert_eq!(stack.transforms[0], Affine::identity());
+        let (x, y) = stack.transforms[1].apply(50.0, 60.0);
+        assert!((x - 53.0).abs() < 0.5 && (y - 62.0).abs() < 0.5, "{:?}: {:?}", alignment, stack.transforms[1]);
+
+        // Away from the seam and the edges every pixel comes from the sharp
+        // frame and matches the scene.
+        let mut differences = Vec::new();
+        for y in 12..(rows - 12) {
+            for x in (12..(cols/2 - 12)).chain((cols/2 + 12)..(cols - 12)) {
+                let i = y*cols + x;
+                assert_eq!(stack.depth.pixels[i], if x < cols/2 { 0 } else { 1 }, "{:?} at ({}, {})", alignment, x, y);
+                differences.push((stack.image.pixels[i] as f32 - sharp.pixels[i] as f32).abs());
+            }
+        }
+        let mean = differences.iter().sum::<f32>()/differences.len() as f32;
+        assert!(mean < 3.0, "{:?}: mean difference {}", alignment, mean);
+    }
+}
+
+#[test]
+fn unrelated_frames_stay_unaligned() {
+    let first = scene(160, 120);
+    let second = Mat::load_from_vec(first.pixels.iter().rev().cloned().collect(), 160, 120, 1);
+    let stack = focus_stack_with(&[first, second], &FocusStackOptions::default());
+    assert_eq!(stack.transforms[1], Affine::identity());
+}
+
+#[test]
+fn affine_fit_and_ransac_round_trip() {
+    let transform = Affine { a: 0.98, b: -0.12, c: 0.09, d: 1.03, tx: 12.5, ty: -7.25 };
+    let points: Vec<(f32, f32)> = (0..40).map(|i| ((i%8) as f32*25.0 + (i/8) as f32*3.0, (i/8) as f32*30.0 + (i%8) as f32)).collect();
+    let mut pairs: Vec<_> = points.iter().map(|&(x, y)| ((x, y), transform.apply(x, y))).collect();
+
+    let fitted = Affine::fit(&pairs).unwrap();
+    for &((x, y), (to_x, to_y)) in pairs.iter() {
+        let (px, py) = fitted.apply(x, y);
+        assert!((px - to_x).abs() < 1e-3 && (py - to_y).abs() < 1e-3);
+    }
+    let inverse = fitted.inverse().unwrap();
+    let (x, y) = inverse.apply(fitted.apply(31.0, 77.0).0, fitted.apply(31.0, 77.0).1);
+    assert!((x - 31.0).abs() < 1e-3 && (y - 77.0).abs() < 1e-3);
+
+    // Every fourth pair points somewhere else.
+    for (i, pair) in pairs.iter_mut().enumerate().filter(|(i, _)| i%4 == 0) {
+        pair.1 = ((i*37%200) as f32, (i*53%150) as f32);
+    }
+    let (found, inliers) = Affine::ransac(&pairs, 1.0, 200).unwrap();
+    assert_eq!(inliers, (0..40).filter(|i| i%4 != 0).collect::<Vec<_>>());
+    for (found, expected) in [found.a, found.b, found.c, found.d].iter().zip([transform.a, transform.b, transform.c, transform.d].iter()) {
+        assert!((found - expected).abs() < 1e-3);
+    }
+    assert!((found.tx - transform.tx).abs() < 1e-2 && (found.ty - transform.ty).abs() < 1e-2);
+
+    assert!(Affine::fit(&pairs[..2]).is_none());
+    let collinear: Vec<_> = (0..5).map(|i| ((i as f32, i as f32), (i as f32, 0.0))).collect();
+    assert!(Affine::fit(&collinear).is_none());
+}
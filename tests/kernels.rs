@@ -0,0 +1,266 @@
+use eva_lib::backend::{Backend, CpuBackend};
+use eva_lib::mat::float_mat::FloatMat;
+use eva_lib::mat::kernels::{BorderMode, Kernel, Pass};
+
+const BORDERS: [BorderMode; 5] = [
+    BorderMode::Constant(0.0),
+    BorderMode::Constant(37.5),
+    BorderMode::Replicate,
+    BorderMode::Reflect,
+    BorderMode::Wrap,
+];
+
+fn noisy(cols: usize, rows: usize, channels: usize) -> FloatMat {
+    let values = (0..cols*rows*channels).map(|i| {
+        let h = (i as u32).wrapping_mul(2654435761);
+        ((h ^ (h >> 16)) & 0xff) as f32
+    }).collect();
+    FloatMat::new(cols, rows, channels, values)
+}
+
+// Direct 2D correlation of the whole kernel, outside samples read through
+// `border`.
+fn dense(mat: &FloatMat, kernel: &Kernel, border: BorderMode) -> Vec<f32> {
+    let (anchor_x, anchor_y) = (kernel.anchor.0 as i64, kernel.anchor.1 as i64);
+    let mut result = Vec::with_capacity(mat.values.len());
+    for y in 0..mat.rows as i64 {
+        for x in 0..mat.cols as i64 {
+            for channel in 0..mat.channels {
+                let mut sum = 0.0;
+                for (ky, row) in kernel.data.iter().enumerate() {
+                    for (kx, weight) in row.iter().enumerate() {
+                        let sx = border.index(x + kx as i64 - anchor_x, mat.cols);
+                        let sy = border.index(y + ky as i64 - anchor_y, mat.rows);
+                        let sample = match (sx, sy) {
+                            (Some(sx), Some(sy)) => mat.get(sx, sy, channel),
+                            _ => border.constant()
+                        };
+                        sum += weight*sample;
+                    }
+                }
+                result.push(sum);
+            }
+        }
+    }
+    result
+}
+
+fn assert_close(actual: &[f32], expected: &[f32], what: &str) {
+    assert_eq!(actual.len(), expected.len(), "{}", what);
+    for (i, (a, e)) in actual.iter().zip(expected.iter()).enumerate() {
+        assert!((a - e).abs() <= 1e-3*e.abs().max(1.0), "{}: sample {} is {}, expected {}", what, i, a, e);
+    }
+}
+
+#[test]
+fn border_index_mapping() {
+    let read = |border: BorderMode| -> Vec<Option<usize>> { (-6..10).map(|i| border.index(i, 4)).collect() };
+
+    let inside: Vec<Option<usize>> = (0..4).map(Some).collect();
+    let constant = read(BorderMode::Constant(3.0));
+    assert!(constant[..6].iter().chain(constant[10..].iter()).all(|i| i.is_none()));
+    assert_eq!(&constant[6..10], &inside[..]);
+
+    // aaa|abcd|ddd
+    assert_eq!(read(BorderMode::Replicate), [0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 3, 3, 3, 3, 3, 3].iter().map(|i| Some(*i)).collect::<Vec<_>>());
+    // cba|abcd|dcb, and mirrored again past the far edge.
+    assert_eq!(read(BorderMode::Reflect), [2, 3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0, 1].iter().map(|i| Some(*i)).collect::<Vec<_>>());
+    // bcd|abcd|abc
+    assert_eq!(read(BorderMode::Wrap), [2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1].iter().map(|i| Some(*i)).collect::<Vec<_>>());
+
+    assert_eq!(BorderMode::Constant(3.0).constant(), 3.0);
+    assert_eq!(BorderMode::Reflect.constant(), 0.0);
+    assert_eq!(BorderMode::Reflect.index(0, 1), Some(0));
+    assert_eq!(BorderMode::Reflect.index(-3, 1), Some(0));
+    assert_eq!(BorderMode::Wrap.index(5, 1), Some(0));
+}
+
+#[test]
+fn separate_recovers_rank_one_kernels() {
+    let column = [1.0, 2.0, -1.0];
+    let row = [0.5, 3.0, 1.0, -2.0, 0.25];
+    let values: Vec<f32> = column.iter().flat_map(|c| row.iter().map(move |r| c*r)).collect();
+    let kernel = Kernel::from_values(5, 3, &values);
+    let (c, r) = kernel.separate().expect("an outer product is rank-1");
+    for (y, weights) in kernel.data.iter().enumerate() {
+        for (x, weight) in weights.iter().enumerate() {
+            assert!((c[y]*r[x] - weight).abs() < 1e-5);
+        }
+    }
+
+    assert!(Kernel::sobel_x().separate().is_some());
+    assert!(Kernel::gaussian(1.5).separate().is_some());
+    assert!(Kernel::laplation_4().separate().is_none());
+    assert!(Kernel::emboss().separate().is_none());
+    assert!(Kernel::from_values(2, 2, &[0.0; 4]).separate().is_none());
+}
+
+#[test]
+fn passes_rescale_the_constant_of_the_second_pass() {
+    let kernel = Kernel::from_values(3, 2, &[1.0, 2.0, 3.0, 2.0, 4.0, 6.0]).with_anchor(2, 1);
+    let passes = kernel.passes(BorderMode::Constant(10.0));
+    assert_eq!(passes.len(), 2);
+    let (horizontal, vertical) = (&passes[0], &passes[1]);
+    assert_eq!((horizontal.width, horizontal.height, horizontal.anchor), (3, 1, (2, 0)));
+    assert_eq!((vertical.width, vertical.height, vertical.anchor), (1, 2, (0, 1)));
+    let row_sum: f32 = horizontal.values.iter().sum();
+    assert_eq!(vertical.border, BorderMode::Constant(10.0*row_sum));
+
+    assert_eq!(kernel.passes(BorderMode::Wrap)[1].border, BorderMode::Wrap);
+    // Single rows, single columns and full rank kernels run in one pass.
+    assert_eq!(Kernel::from_values(3, 1, &[1.0, 2.0, 3.0]).passes(BorderMode::Reflect).len(), 1);
+    assert_eq!(Kernel::laplation_8().passes(BorderMode::Reflect).len(), 1);
+}
+
+#[test]
+fn separable_and_dense_execution_agree() {
+    let column = [1.0, 2.0, -1.0];
+    let row = [0.5, 3.0, 1.0, -2.0, 0.25];
+    let values: Vec<f32> = column.iter().flat_map(|c| row.iter().map(move |r| c*r)).collect();
+    let kernels = [
+        Kernel::from_values(5, 3, &values),
+        Kernel::from_values(5, 3, &values).with_anchor(4, 0),
+        Kernel::gaussian(1.2),
+        Kernel::box_blur(4, 2).with_anchor(0, 1),
+        Kernel::sobel_y(),
+    ];
+    // The last two sizes are smaller than some kernels, so borders read
+    // past more than one period.
+    for &(cols, rows, channels) in &[(17, 13, 1), (9, 11, 3), (3, 2, 1), (2, 4, 2)] {
+        let mat = noisy(cols, rows, channels);
+        for kernel in kernels.iter() {
+            assert_eq!(kernel.passes(BorderMode::Replicate).len(), 2);
+            for border in BORDERS.iter() {
+                let what = format!("{}x{}x{} {:?} {:?}", cols, rows, channels, kernel.data, border);
+                let expected = dense(&mat, kernel, *border);
+                let separable = mat.convolute(kernel, *border);
+                assert_close(&separable.values, &expected, &what);
+
+                let single = Pass { values: kernel.flatten(), width: kernel.width(), height: kernel.height(), anchor: kernel.anchor, border: *border };
+                let one_pass = CpuBackend.correlate(&mat.values, cols, rows, channels, &single).unwrap();
+                assert_close(&one_pass, &expected, &what);
+            }
+        }
+    }
+}
+
+#[test]
+fn non_separable_kernels_match_the_direct_sum() {
+    let mat = noisy(12, 9, 2);
+    for kernel in [Kernel::laplation_12(), Kernel::emboss().with_anchor(0, 2)].iter() {
+        for border in BORDERS.iter() {
+            let what = format!("{:?} {:?}", kernel.data, border);
+            assert_close(&mat.convolute(kernel, *border).values, &dense(&mat, kernel, *border), &what);
+        }
+    }
+}
+
+fn assert_symmetric(kernel: &Kernel) {
+    let (width, height) = (kernel.width(), kernel.height());
+    for y in 0..height {
+        for x in 0..width {
+            let value = kernel.data[y][x];
+            assert!((value - kernel.data[height - 1 - y][x]).abs() < 1e-6);
+            assert!((value - kernel.data[y][width - 1 - x]).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn constructors_have_the_documented_shapes() {
+    for &sigma in &[0.5, 1.0, 1.7, 3.0] {
+        let kernel = Kernel::gaussian(sigma);
+        let size = 2*(3.0*sigma).ceil() as usize + 1;
+        assert_eq!((kernel.width(), kernel.height(), kernel.anchor), (size, size, (size/2, size/2)));
+        assert!((kernel.sum() - 1.0).abs() < 1e-5);
+        assert_symmetric(&kernel);
+        let peak = kernel.data[size/2][size/2];
+        assert!(kernel.data.iter().flatten().all(|v| *v > 0.0 && *v <= peak));
+    }
+    assert_eq!(Kernel::gaussian(0.0).data, vec![vec![1.0]]);
+    let even = Kernel::gaussian_sized(4, 1.0);
+    assert_symmetric(&even);
+    assert_eq!(even.anchor, (2, 2));
+
+    let mean = Kernel::box_blur(3, 2);
+    assert_eq!((mean.width(), mean.height(), mean.anchor), (3, 2, (1, 1)));
+    assert!(mean.flatten().iter().all(|v| (v - 1.0/6.0).abs() < 1e-6));
+
+    for kernel in [Kernel::laplation_4(), Kernel::laplation_8(), Kernel::laplation_12(), Kernel::sobel_x(), Kernel::scharr_y()].iter() {
+        assert!(kernel.sum().abs() < 1e-6);
+    }
+    for kernel in [Kernel::sharpen(), Kernel::unsharp(1.0, 0.7), Kernel::emboss()].iter() {
+        assert!((kernel.sum() - 1.0).abs() < 1e-5);
+    }
+    let unsharp = Kernel::unsharp(1.0, 0.7);
+    let (x, y) = unsharp.anchor;
+    assert!(unsharp.data[y][x] > 1.0);
+    assert!(unsharp.flatten().iter().enumerate().all(|(i, v)| i == y*unsharp.width() + x || *v < 0.0));
+
+    assert_eq!(Kernel::sobel_y().flatten(), Kernel::sobel_x().transposed().flatten());
+    assert_eq!(Kernel::scharr_y().flatten(), vec![-3.0, -10.0, -3.0, 0.0, 0.0, 0.0, 3.0, 10.0, 3.0]);
+    assert_eq!(Kernel::sobel_x().max_response(), 4.0);
+    assert_eq!(Kernel::laplation_8().normalized().sum(), 0.0);
+    assert!((Kernel::sobel_x().normalized_l1().flatten().iter().map(|v| v.abs()).sum::<f32>() - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn motion_blur_follows_the_angle() {
+    let lit = |kernel: &Kernel| -> Vec<(usize, usize)> {
+        let mut points = Vec::new();
+        for (y, row) in kernel.data.iter().enumerate() {
+            for (x, value) in row.iter().enumerate() {
+                if *value > 0.0 {
+                    points.push((x, y));
+                }
+            }
+        }
+        points
+    };
+    assert_eq!(lit(&Kernel::motion_blur(5, 0.0)), (0..5).map(|x| (x, 2)).collect::<Vec<_>>());
+    assert_eq!(lit(&Kernel::motion_blur(5, 90.0)), (0..5).map(|y| (2, y)).collect::<Vec<_>>());
+    // Counter-clockwise with y down: from the bottom left to the top right.
+    assert_eq!(lit(&Kernel::motion_blur(5, 45.0)), (0..5).map(|y| (4 - y, y)).collect::<Vec<_>>());
+    for &(length, angle) in &[(7, 30.0), (9, 120.0), (4, 200.0), (1, 10.0)] {
+        let kernel = Kernel::motion_blur(length, angle);
+        assert_eq!((kernel.width(), kernel.height()), (length, length));
+        assert_eq!(lit(&kernel).len(), length);
+        assert!((kernel.sum() - 1.0).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn anchors_place_the_kernel_over_the_output() {
+    let mat = noisy(8, 6, 1);
+    // A single weight at (2, 1) reads the sample 2 - ax, 1 - ay away.
+    let mut values = vec![0.0; 4*3];
+    values[4 + 2] = 1.0;
+    for &(anchor_x, anchor_y) in &[(0, 0), (2, 1), (3, 2), (1, 0)] {
+        let kernel = Kernel::from_values(4, 3, &values).with_anchor(anchor_x, anchor_y);
+        let result = mat.convolute(&kernel, BorderMode::Wrap);
+        for y in 0..mat.rows {
+            for x in 0..mat.cols {
+                let sx = BorderMode::Wrap.index(x as i64 + 2 - anchor_x as i64, mat.cols).unwrap();
+                let sy = BorderMode::Wrap.index(y as i64 + 1 - anchor_y as i64, mat.rows).unwrap();
+                assert_eq!(result.get(x, y, 0), mat.get(sx, sy, 0));
+            }
+        }
+    }
+
+    let kernel = Kernel::from_values(3, 2, &[1.0; 6]).with_anchor(2, 0);
+    let transposed = kernel.transposed();
+    assert_eq!((transposed.width(), transposed.height(), transposed.anchor), (2, 3, (0, 2)));
+    assert_eq!(Kernel::load(vec![vec![1.0; 4]; 2]).anchor, (2, 1));
+}
+
+#[test]
+#[should_panic]
+fn from_values_checks_the_count() {
+    Kernel::from_values(3, 3, &[1.0; 8]);
+}
+
+#[test]
+#[should_panic]
+fn anchors_must_be_inside_the_kernel() {
+    Kernel::box_blur(3, 2).with_anchor(1, 2);
+}
@@ -0,0 +1,72 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use eva_lib::mat::descriptor::BinaryDescriptor;
+use eva_lib::mat::lsh::{LshIndex, LshParams};
+use eva_lib::mat::matcher;
+use eva_lib::mat::pixel_description::PixelDescription;
+
+fn described(bits: &[u8]) -> PixelDescription {
+    let mut description = PixelDescription::new();
+    description.descriptor = BinaryDescriptor::from_pairs(bits);
+    description
+}
+
+#[test]
+fn knn_with_zero_k_is_empty() {
+    let train = vec![described(&[1, 0, 0]), described(&[1, 1, 1])];
+    let index = LshIndex::from_descriptions(&train, LshParams { key_bits: 2, ..LshParams::default() });
+    assert!(index.knn(&train[0].descriptor, 0).is_empty());
+
+    let queries = vec![described(&[1, 0, 1]), described(&[0, 0, 1])];
+    let matches = index.knn_match(&queries, 0);
+    assert_eq!(matches.len(), 2);
+    assert!(matches.iter().all(|nearest| nearest.is_empty()));
+}
+
+#[test]
+fn knn_recall_against_brute_force() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let random_bits = |rng: &mut StdRng| -> Vec<u8> { (0..256).map(|_| rng.gen_range(0, 2)).collect() };
+    let train_bits: Vec<Vec<u8>> = (0..1000).map(|_| random_bits(&mut rng)).collect();
+    let train: Vec<PixelDescription> = train_bits.iter().map(|bits| described(bits)).collect();
+    // Noisy copies of every fifth train descriptor, with 5 to 30 bits flipped,
+    // and unrelated descriptors.
+    let queries: Vec<PixelDescription> = (0..200).map(|i| {
+        if i%4 == 3 {
+            return described(&random_bits(&mut rng));
+        }
+        let mut bits = train_bits[i*5].clone();
+        for _ in 0..(5 + i%26) {
+            let bit = rng.gen_range(0, 256);
+            bits[bit] ^= 1;
+        }
+        described(&bits)
+    }).collect();
+
+    let index = LshIndex::from_descriptions(&train, LshParams::default());
+    let approximate = index.knn_match(&queries, 2);
+    let exact = matcher::knn_match(&queries, &train, 2);
+
+    let (mut near, mut found) = (0, 0);
+    for (approximate, exact) in approximate.iter().zip(exact.iter()) {
+        // Whatever the index returns is a real neighbour with its true
+        // distance, in order.
+        for m in approximate {
+            assert_eq!(m.distance, queries[m.query].descriptor.hamming(&train[m.train].descriptor));
+        }
+        assert!(approximate.windows(2).all(|pair| pair[0].distance <= pair[1].distance));
+        if let (Some(best), Some(expected)) = (approximate.first(), exact.first()) {
+            assert!(best.distance >= expected.distance);
+        }
+
+        if exact[0].distance <= 30 {
+            near += 1;
+            if approximate.first().map(|m| m.distance) == Some(exact[0].distance) {
+                found += 1;
+            }
+        }
+    }
+    assert_eq!(near, 150);
+    assert!(found*100 >= near*90, "found {} of {} near neighbours", found, near);
+}
@@ -0,0 +1,19 @@
+use eva_lib::mat::descriptor::BinaryDescriptor;
+use eva_lib::mat::matcher;
+use eva_lib::mat::pixel_description::PixelDescription;
+
+fn described(bits: &[u8]) -> PixelDescription {
+    let mut description = PixelDescription::new();
+    description.descriptor = BinaryDescriptor::from_pairs(bits);
+    description
+}
+
+#[test]
+fn knn_match_with_zero_k_is_empty() {
+    let queries = vec![described(&[1, 0, 1]), described(&[0, 0, 1])];
+    let train = vec![described(&[1, 0, 0]), described(&[1, 1, 1])];
+    let matches = matcher::knn_match(&queries, &train, 0);
+    assert_eq!(matches.len(), 2);
+    assert!(matches.iter().all(|nearest| nearest.is_empty()));
+    assert_eq!(matcher::knn_match(&queries, &train, 1)[0][0].distance, 1);
+}
@@ -0,0 +1,89 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use eva_lib::mat::nms::{self, NmsMode, NmsOptions};
+use eva_lib::mat::pixel_description::PixelDescription;
+
+fn keypoint(x: usize, y: usize, response: f32, removed: bool) -> PixelDescription {
+    let mut description = PixelDescription::new();
+    description.coordinate = (x, y);
+    description.response = response;
+    description.removed = removed;
+    description
+}
+
+// The O(N²) suppression `suppress_radius` replaced: every keypoint removes
+// the others within `radius` on both axes whose response is not greater.
+fn pairwise(descriptions: &[PixelDescription], radius: usize) -> Vec<PixelDescription> {
+    let mut descriptions = descriptions.to_vec();
+    let r = radius as i64;
+    let len = descriptions.len();
+    for desc_i in 0..len {
+        for other_i in 0..len {
+            if descriptions[other_i].coordinate != descriptions[desc_i].coordinate {
+                let xr = (descriptions[desc_i].coordinate.0 as i64 - descriptions[other_i].coordinate.0 as i64).abs();
+                let yr = (descriptions[desc_i].coordinate.1 as i64 - descriptions[other_i].coordinate.1 as i64).abs();
+                if xr <= r && yr <= r && descriptions[desc_i].response >= descriptions[other_i].response {
+                    descriptions[other_i].remove();
+                }
+            }
+        }
+    }
+    descriptions.into_iter().filter(|d| !d.removed).collect()
+}
+
+fn random_keypoints(rng: &mut StdRng, count: usize, cols: usize, rows: usize, levels: u32) -> Vec<PixelDescription> {
+    (0..count).map(|_| {
+        // Few response levels give plenty of ties.
+        let response = rng.gen_range(0, levels) as f32;
+        keypoint(rng.gen_range(0, cols), rng.gen_range(0, rows), response, rng.gen_range(0, 10) == 0)
+    }).collect()
+}
+
+fn coordinates(descriptions: &[PixelDescription]) -> Vec<((usize, usize), f32)> {
+    descriptions.iter().map(|d| (d.coordinate, d.response)).collect()
+}
+
+#[test]
+fn suppress_radius_matches_pairwise_suppression() {
+    let mut rng = StdRng::seed_from_u64(34);
+    for &(count, cols, rows, levels) in &[(400, 64, 48, 1000), (600, 40, 30, 4), (300, 500, 20, 50), (50, 8, 8, 3), (1, 10, 10, 5)] {
+        let descriptions = random_keypoints(&mut rng, count, cols, rows, levels);
+        for &radius in &[0, 1, 2, 3, 7, 25] {
+            let expected = pairwise(&descriptions, radius);
+            let kept = nms::suppress_radius(&descriptions, radius);
+            assert_eq!(coordinates(&kept), coordinates(&expected), "{} keypoints, radius {}", count, radius);
+        }
+    }
+    assert!(nms::suppress_radius(&[], 2).is_empty());
+}
+
+#[test]
+fn suppress_radius_handles_duplicates_and_removed_keypoints() {
+    let descriptions = vec![
+        keypoint(5, 5, 3.0, false),
+        // Same place and response: neither suppresses the other.
+        keypoint(5, 5, 3.0, false),
+        keypoint(6, 5, 3.0, false),
+        // Removed, but still suppresses its weaker neighbour.
+        keypoint(20, 20, 9.0, true),
+        keypoint(21, 22, 4.0, false),
+        keypoint(30, 30, 1.0, false),
+    ];
+    let kept = nms::suppress_radius(&descriptions, 2);
+    assert_eq!(coordinates(&kept), coordinates(&pairwise(&descriptions, 2)));
+    assert_eq!(coordinates(&kept), vec![((30, 30), 1.0)]);
+}
+
+#[test]
+fn default_options_keep_the_old_nms_result() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let descriptions = random_keypoints(&mut rng, 500, 80, 60, 20);
+    let kept = nms::suppress(&descriptions, &NmsOptions::default());
+    assert_eq!(coordinates(&kept), coordinates(&pairwise(&descriptions, 2)));
+
+    let retained = nms::suppress(&descriptions, &NmsOptions { radius: 2, mode: NmsMode::Radius, retain: Some(10) });
+    assert_eq!(retained.len(), 10.min(kept.len()));
+    let weakest_kept = retained.iter().map(|d| d.response).fold(f32::INFINITY, f32::min);
+    assert!(kept.iter().filter(|d| d.response > weakest_kept).count() <= 10);
+}
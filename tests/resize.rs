@@ -0,0 +1,20 @@
+use eva_lib::mat::Mat;
+
+#[test]
+fn resize_of_empty_image_is_black() {
+    let empty = Mat::load_from_vec(Vec::new(), 0, 0, 3);
+    let resized = empty.resize(4, 3);
+    assert_eq!((resized.cols, resized.rows, resized.bytes_per_pixel), (4, 3, 3));
+    assert_eq!(resized.pixels, vec![0; 36]);
+
+    let no_rows = Mat::load_from_vec(Vec::new(), 5, 0, 1);
+    assert_eq!(no_rows.resize(2, 2).pixels, vec![0; 4]);
+    assert!(no_rows.resize(0, 0).pixels.is_empty());
+}
+
+#[test]
+fn resize_to_the_same_size_keeps_pixels() {
+    let pixels: Vec<u8> = (0..7*5*3).map(|i| (i*37%256) as u8).collect();
+    let mat = Mat::load_from_vec(pixels.clone(), 7, 5, 3);
+    assert_eq!(mat.resize(7, 5).pixels, pixels);
+}